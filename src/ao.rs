@@ -0,0 +1,219 @@
+// Ambient occlusion baked per vertex: how much of the hemisphere above each vertex is hidden by
+// the room around it. Rays are cast in the background and the result is cached on disk per mesh.
+use crate::bvh::{geometry_hash, StableHasher};
+use crate::colorize::vertex_normals;
+use crate::room::asset_dir;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use std::hash::Hasher;
+use std::path::PathBuf;
+
+const CACHE_DIR: &str = "ao_cache"; // in the assets folder
+const CACHE_VERSION: u32 = 2; // bump when the bake changes, so stale results aren't reused
+
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub rays: u32,
+    pub radius: f32, // how far to look for occluders, as a fraction of the mesh's size
+    pub strength: f32, // how dark a fully occluded vertex gets
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            rays: 32,
+            radius: 0.1,
+            strength: 0.7,
+        }
+    }
+}
+
+impl AmbientOcclusion {
+    /// Color multiplier for a vertex with `occlusion` of its rays blocked
+    pub fn shade(&self, occlusion: f32) -> f32 {
+        1.0 - self.strength.clamp(0.0, 1.0) * occlusion
+    }
+}
+
+/// `n` directions over the hemisphere around +Z, denser toward the pole like diffuse light
+fn hemisphere(n: u32) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    (0..n)
+        .map(|i| {
+            let u = (i as f32 + 0.5) / n as f32;
+            let (r, phi) = (u.sqrt(), i as f32 * golden_angle);
+            Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt())
+        })
+        .collect()
+}
+
+/// Fraction of each vertex's rays that hit an occluder within the radius. The mesh sits at
+/// `transform`, `occluders` gives the world space distance to the closest surface along a ray.
+pub fn bake(
+    mesh: &Mesh,
+    transform: &GlobalTransform,
+    occluders: impl Fn(Ray) -> Option<f32>,
+    settings: &AmbientOcclusion,
+) -> Option<Vec<f32>> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+    let to_world = transform.compute_matrix();
+    let normal_matrix = Mat3::from_mat4(to_world).inverse().transpose();
+    let normals: Vec<Vec3> = vertex_normals(mesh, positions)
+        .iter()
+        .map(|n| (normal_matrix * *n).normalize_or_zero())
+        .collect();
+    let positions: Vec<Vec3> = positions
+        .iter()
+        .map(|p| to_world.transform_point3(Vec3::from(*p)))
+        .collect();
+    let (min, max) = positions
+        .iter()
+        .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+            (min.min(*p), max.max(*p))
+        });
+    let size = (max - min).length();
+    let (radius, bias) = (settings.radius * size, size * 1e-4);
+    let directions = hemisphere(settings.rays.max(1));
+    Some(
+        positions
+            .iter()
+            .zip(&normals)
+            .map(|(p, n)| {
+                if *n == Vec3::ZERO {
+                    return 0.0;
+                }
+                let rotation = Quat::from_rotation_arc(Vec3::Z, *n);
+                let origin = *p + *n * bias;
+                let hits = directions
+                    .iter()
+                    .filter(|d| {
+                        occluders(Ray {
+                            origin,
+                            direction: rotation * **d,
+                        })
+                        .is_some_and(|t| t > bias && t < radius)
+                    })
+                    .count();
+                hits as f32 / directions.len() as f32
+            })
+            .collect(),
+    )
+}
+
+/// Changes whenever the mesh's shape or place, the occluders or the ray settings do,
+/// `occluders` being a stable hash of their shapes and places
+pub fn cache_key(
+    mesh: &Mesh,
+    transform: &GlobalTransform,
+    occluders: u64,
+    settings: &AmbientOcclusion,
+) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write_u32(CACHE_VERSION);
+    hasher.write_u64(geometry_hash(mesh));
+    for v in transform.compute_matrix().to_cols_array() {
+        hasher.write_u32(v.to_bits());
+    }
+    hasher.write_u64(occluders);
+    hasher.write_u32(settings.rays);
+    hasher.write_u32(settings.radius.to_bits());
+    hasher.finish()
+}
+
+fn cache_path(key: u64) -> PathBuf {
+    asset_dir().join(CACHE_DIR).join(format!("{key:016x}.json"))
+}
+
+pub fn load_cached(key: u64) -> Option<Vec<f32>> {
+    let file = std::fs::read_to_string(cache_path(key)).ok()?;
+    serde_json::from_str(&file).ok()
+}
+
+pub fn store_cached(key: u64, occlusion: &[f32]) {
+    let write = || -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(asset_dir().join(CACHE_DIR))?;
+        std::fs::write(cache_path(key), serde_json::to_string(occlusion)?)?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        eprintln!("failed to cache ambient occlusion: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use bevy::render::render_resource::PrimitiveTopology;
+
+    #[test]
+    fn hemisphere_points_up() {
+        let directions = hemisphere(64);
+        assert_eq!(directions.len(), 64);
+        for d in &directions {
+            assert!(d.z > 0.0);
+            assert!((d.length() - 1.0).abs() < 1e-5);
+        }
+        // spread out: the average leans up but not all the way
+        let mean = directions.iter().sum::<Vec3>() / 64.0;
+        assert!(mean.x.abs() < 0.05 && mean.y.abs() < 0.05);
+        assert!(mean.z > 0.5 && mean.z < 0.8);
+    }
+
+    #[test]
+    fn corners_are_darker() {
+        // floor in front of a wall standing at x = 0
+        let floor = [[1.0, 0.0, 1.0], [1.0, 0.0, 9.0], [9.0, 0.0, 1.0]];
+        let wall = [[0.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]];
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let positions: Vec<[f32; 3]> = floor.into_iter().chain(wall).collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            vec![[0.0, 1.0, 0.0]; 3]
+                .into_iter()
+                .chain(vec![[1.0, 0.0, 0.0]; 3])
+                .collect::<Vec<_>>(),
+        );
+        let settings = AmbientOcclusion {
+            radius: 0.5,
+            ..default()
+        };
+        let placed = GlobalTransform::from_translation(Vec3::new(5.0, 1.0, 0.0));
+        let bvh = Bvh::from_mesh(&mesh).unwrap();
+        let room = |ray: Ray| Some(bvh.intersect_world(ray, &placed)?.t);
+        let occlusion = bake(&mesh, &placed, room, &settings).unwrap();
+        // the floor vertex in the corner sees the wall, the far one sees nothing
+        assert!(occlusion[0] > 0.2);
+        assert_eq!(occlusion[2], 0.0);
+        assert!(settings.shade(occlusion[0]) < settings.shade(occlusion[2]));
+        // nothing else in the room, nothing to hide behind
+        let alone = bake(&mesh, &placed, |_| None, &settings).unwrap();
+        assert!(alone.iter().all(|o| *o == 0.0));
+        // same mesh, place, room and rays, same cache entry
+        assert_eq!(
+            cache_key(&mesh, &placed, 1, &settings),
+            cache_key(&mesh.clone(), &placed, 1, &settings)
+        );
+        let more_rays = AmbientOcclusion {
+            rays: 64,
+            ..settings
+        };
+        assert_ne!(
+            cache_key(&mesh, &placed, 1, &settings),
+            cache_key(&mesh, &placed, 1, &more_rays)
+        );
+        assert_ne!(
+            cache_key(&mesh, &placed, 1, &settings),
+            cache_key(&mesh, &placed, 2, &settings)
+        );
+        assert_ne!(
+            cache_key(&mesh, &placed, 1, &settings),
+            cache_key(&mesh, &GlobalTransform::IDENTITY, 1, &settings)
+        );
+    }
+}
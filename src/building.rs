@@ -0,0 +1,219 @@
+// A project can hold several rooms over several floors. Lamps and ghosts belong to the room
+// they stand in, and only one floor is shown at a time unless they're stacked.
+use crate::bulb::{Ghost, Lamp};
+use crate::fade::Faded;
+use crate::hover::egui_wants_keyboard;
+use crate::room::{asset_dir, Room, RoomBounds, RoomConfig, RoomMesh};
+use anyhow::Error;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[reflect(Resource)]
+pub struct Building {
+    pub rooms: Vec<RoomConfig>,
+}
+
+impl Default for Building {
+    fn default() -> Self {
+        Self {
+            rooms: vec![RoomConfig::default()],
+        }
+    }
+}
+
+impl Building {
+    /// `path` is relative to the assets folder, like the rooms in it, or absolute
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::read_to_string(asset_dir().join(path))?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    /// `--building building.json`, otherwise a single room from the room arguments
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let args: Vec<String> = args.into_iter().collect();
+        if let Some(i) = args.iter().position(|a| a == "--building") {
+            let path = args
+                .get(i + 1)
+                .ok_or_else(|| anyhow::anyhow!("--building needs a value"))?;
+            return Self::load(path);
+        }
+        Ok(RoomConfig::from_args(args)?
+            .map_or_else(Self::default, |room| Self { rooms: vec![room] }))
+    }
+
+    fn levels(&self) -> impl Iterator<Item = i32> + '_ {
+        self.rooms.iter().map(|r| r.level)
+    }
+}
+
+/// Room a lamp or ghost is in; stays put when it wanders outside every room
+#[derive(Component, Clone, Copy, PartialEq)]
+pub struct InRoom(pub Entity);
+
+/// PageUp/PageDown walk between floors, V stacks all of them with the upper ones faded
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct FloorView {
+    pub level: i32,
+    pub stacked: bool,
+    pub fade: f32, // opacity of the floors above `level` when stacked
+}
+
+impl Default for FloorView {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            stacked: false,
+            fade: 0.2,
+        }
+    }
+}
+
+/// How a room on `level` is drawn: `None` when hidden, otherwise its opacity
+fn room_opacity(view: &FloorView, level: i32) -> Option<f32> {
+    match (view.stacked, level.cmp(&view.level)) {
+        (_, std::cmp::Ordering::Equal) => Some(1.0),
+        (false, _) => None,
+        (true, std::cmp::Ordering::Less) => Some(1.0),
+        (true, std::cmp::Ordering::Greater) => Some(view.fade),
+    }
+}
+
+/// Lamps and ghosts, the things that move around inside rooms
+type Occupant = Or<(With<Lamp>, With<Ghost>)>;
+/// Moved since their room was last checked, or never checked
+type Unplaced = Or<(Changed<GlobalTransform>, Without<InRoom>)>;
+
+pub fn assign_rooms(
+    mut commands: Commands,
+    bounds: Res<RoomBounds>,
+    occupants: Query<(Entity, &GlobalTransform, Option<&InRoom>), (Occupant, Unplaced)>,
+) {
+    for (entity, transform, current) in occupants.iter() {
+        let point = transform.translation();
+        // where rooms overlap, stay in the one it's in
+        if current.is_some_and(|c| bounds.contains(c.0, point)) {
+            continue;
+        }
+        let Some(room) = bounds.room_at(point) else {
+            continue;
+        };
+        if current.map(|c| c.0) != Some(room) {
+            commands.entity(entity).insert(InRoom(room));
+        }
+    }
+}
+
+fn switch_floor(keys: Res<Input<KeyCode>>, building: Res<Building>, mut view: ResMut<FloorView>) {
+    let (lowest, highest) = (
+        building.levels().min().unwrap_or(0),
+        building.levels().max().unwrap_or(0),
+    );
+    if keys.just_pressed(KeyCode::PageUp) {
+        view.level = (view.level + 1).min(highest);
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        view.level = (view.level - 1).max(lowest);
+    }
+    if keys.just_pressed(KeyCode::V) {
+        view.stacked = !view.stacked;
+    }
+}
+
+fn apply_floor_view(
+    mut commands: Commands,
+    view: Res<FloorView>,
+    mut rooms: Query<(&Room, &mut Visibility), Without<InRoom>>,
+    meshes: Query<(Entity, &RoomMesh, Option<&Faded>)>,
+    mut occupants: Query<(&InRoom, &mut Visibility), (Occupant, Without<Room>)>,
+) {
+    for (room, mut visibility) in rooms.iter_mut() {
+        let shown = room_opacity(&view, room.level).is_some();
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+    for (entity, mesh, faded) in meshes.iter() {
+        let wanted = rooms
+            .get(mesh.room)
+            .ok()
+            .and_then(|(room, _)| room_opacity(&view, room.level))
+            .filter(|opacity| *opacity < 1.0)
+            .map(Faded);
+        match (wanted, faded) {
+            (Some(wanted), Some(faded)) if wanted == *faded => {}
+            (Some(wanted), _) => {
+                commands.entity(entity).insert(wanted);
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<Faded>();
+            }
+            (None, None) => {}
+        }
+    }
+    for (in_room, mut visibility) in occupants.iter_mut() {
+        let shown = rooms
+            .get(in_room.0)
+            .map_or(true, |(room, _)| room_opacity(&view, room.level).is_some());
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
+
+pub struct BuildingPlugin;
+
+impl Plugin for BuildingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<FloorView>()
+            .init_resource::<FloorView>()
+            .add_systems(
+                Update,
+                (
+                    assign_rooms,
+                    switch_floor.run_if(not(egui_wants_keyboard)),
+                    apply_floor_view,
+                ),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn building_from_room_arguments() {
+        assert_eq!(Building::from_args(args("")).unwrap(), Building::default());
+        let building = Building::from_args(args("--room flat.glb")).unwrap();
+        assert_eq!(building.rooms.len(), 1);
+        assert_eq!(building.rooms[0].path, "flat.glb");
+        assert!(Building::from_args(args("--building")).is_err());
+        // found in the assets folder, next to the floor plan it uses
+        let building = Building::from_args(args("--building building.json")).unwrap();
+        assert_eq!(building.levels().collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn floors_hidden_or_faded() {
+        let mut view = FloorView {
+            level: 1,
+            ..default()
+        };
+        assert_eq!(room_opacity(&view, 1), Some(1.0));
+        assert_eq!(room_opacity(&view, 0), None);
+        assert_eq!(room_opacity(&view, 2), None);
+        view.stacked = true;
+        assert_eq!(room_opacity(&view, 0), Some(1.0));
+        assert_eq!(room_opacity(&view, 2), Some(view.fade));
+    }
+}
@@ -1,13 +1,57 @@
-use crate::hover::{Draggable, Hoverable};
+use crate::falloff::{Falloff, FalloffSettings};
+use crate::hover::{egui_wants_keyboard, DragEnd, Draggable, Dragged, Hoverable};
 use crate::hue::BulbState;
+use crate::layout::Layout;
+use crate::occlusion::Occluder;
+use crate::path::GhostPath;
+use crate::room::RoomBounds;
 use crate::util::*;
 use crate::{bulb, hover};
+use bevy::ecs::query::Has;
 use bevy::prelude::*;
 use std::collections::HashSet;
 
 #[derive(Component)]
 pub struct Bulb {
     pub index: u8,
+    pub color: Color, // color read from the bridge, before any ghost tints it
+    pub hue: f64,     // as read from the bridge, 0-1, so it goes back exactly
+}
+
+impl Bulb {
+    /// Ghost colors mixed by how strongly each one pulls on this bulb, uncolored ghosts pull
+    /// towards the bulb's own color. `None` with no colored ghosts left, the bulb is itself again.
+    fn tint(
+        &self,
+        pulls: impl Iterator<Item = (Option<Color>, f32)>,
+        any_colored: bool,
+    ) -> Option<Color> {
+        if !any_colored {
+            return None;
+        }
+        let mut mixed = Vec4::ZERO;
+        for (color, strength) in pulls {
+            let color = color.unwrap_or(self.color);
+            mixed += Vec4::from(color.as_linear_rgba_f32()) * strength.max(0.0);
+        }
+        (mixed.w > 0.0).then(|| {
+            Color::rgba_linear(mixed.x / mixed.w, mixed.y / mixed.w, mixed.z / mixed.w, 1.0)
+        })
+    }
+
+    /// Hue for the bridge, 0-1: the tint's, or the one read from the bridge when untinted
+    fn hue(&self, tint: Option<Color>) -> f64 {
+        match tint.map(|c| c.as_hsla()) {
+            Some(Color::Hsla { hue, .. }) => (hue / 360.0).into(),
+            _ => self.hue,
+        }
+    }
+}
+
+/// Root of a spawned lamp model, the entity that gets dragged around
+#[derive(Component)]
+pub struct Lamp {
+    pub index: u8,
 }
 
 #[derive(Bundle)]
@@ -16,50 +60,316 @@ pub struct BulbBundle {
     pub bulb: Bulb,
 }
 
-#[derive(Component)]
-struct Ghost;
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Ghost {
+    pub weight: f32,          // only used by `GhostBlend::WeightedAverage`
+    pub color: Option<Color>, // bulbs near a colored ghost take on its hue
+}
+
+impl Default for Ghost {
+    fn default() -> Self {
+        Self {
+            weight: 1.0,
+            color: None,
+        }
+    }
+}
+
+/// How bulbs combine the pull of several ghosts into one brightness
+#[derive(Resource, Reflect, Default, Clone, Copy, PartialEq)]
+#[reflect(Resource)]
+pub enum GhostBlend {
+    #[default]
+    Max,
+    SumClamped,
+    Nearest,
+    WeightedAverage,
+}
+
+struct Influence {
+    strength: f32, // 0 - no effect, 1 - ghost is right next to the bulb
+    distance: f32,
+    weight: f32,
+}
+
+impl GhostBlend {
+    fn combine(self, influences: &[Influence]) -> f32 {
+        match self {
+            GhostBlend::Max => influences.iter().map(|i| i.strength).fold(0.0, f32::max),
+            GhostBlend::SumClamped => influences
+                .iter()
+                .map(|i| i.strength)
+                .sum::<f32>()
+                .clamp(0.0, 1.0),
+            GhostBlend::Nearest => influences
+                .iter()
+                .min_by(|a, b| a.distance.total_cmp(&b.distance))
+                .map_or(0.0, |i| i.strength),
+            GhostBlend::WeightedAverage => {
+                // closer and heavier ghosts count for more
+                let w = |i: &Influence| i.weight / i.distance.max(f32::EPSILON);
+                let total: f32 = influences.iter().map(w).sum();
+                if total > 0.0 {
+                    influences.iter().map(|i| w(i) * i.strength).sum::<f32>() / total
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
 
 #[derive(Resource, Reflect)]
-struct IntensityBounds {
+pub(crate) struct IntensityBounds {
     min: f32,
     max: f32,
 }
 
 #[derive(Resource, Reflect)]
-struct DistanceBounds {
+pub(crate) struct DistanceBounds {
     min: f32,
     max: f32,
 }
 
+/// Path a ghost follows through the room.
+/// Phase is advanced by [`GhostMotion::speed`] every frame and fed to one of the `traj_*` functions.
+#[derive(Reflect, Clone, PartialEq, Default)]
+pub enum Trajectory {
+    /// Left where it is, so it can be dragged around with the mouse
+    #[default]
+    Manual,
+    /// Pinned where it is, the mouse can't pick it up
+    Stationary,
+    Orbit {
+        center: Vec3,
+        radius: f32,
+    },
+    Yoyo {
+        start: Vec3,
+        end: Vec3,
+    },
+    Lissajous {
+        center: Vec3,
+        amplitude: Vec3,
+        frequency: Vec3,
+        delta: f32,
+        gamma: f32,
+    },
+    /// Keyframed spline, drawn with the [`crate::path::PathEditor`]
+    Path(GhostPath),
+}
+
+impl Trajectory {
+    /// Position at `phase`, `None` for trajectories that don't move the ghost
+    fn sample(&self, phase: f64) -> Option<Vec3> {
+        match self {
+            Trajectory::Manual | Trajectory::Stationary => None,
+            Trajectory::Orbit { center, radius } => {
+                Some(traj_orbit(phase % PI as f64, *center, *radius as f64))
+            }
+            Trajectory::Yoyo { start, end } => Some(traj_yoyo(phase % PI as f64, *start, *end)),
+            Trajectory::Lissajous {
+                center,
+                amplitude,
+                frequency,
+                delta,
+                gamma,
+            } => Some(
+                *center
+                    + traj_lissajous(
+                        phase % (2.0 * PI as f64), // repeats for integer frequencies
+                        frequency.x as f64,
+                        frequency.y as f64,
+                        frequency.z as f64,
+                        *delta as f64,
+                        *gamma as f64,
+                        amplitude.x as f64,
+                        amplitude.y as f64,
+                        amplitude.z as f64,
+                    ),
+            ),
+            Trajectory::Path(path) => path.sample(phase as f32),
+        }
+    }
+
+    /// Line the trajectory up with `current` so switching to it doesn't make the ghost jump
+    fn anchor(&mut self, phase: &mut f64, current: Vec3) {
+        match self {
+            // paths are drawn at fixed spots in the room, join at the nearest keyframe instead
+            Trajectory::Path(path) => *phase = path.closest_time(current) as f64,
+            _ => {
+                if let Some(pos) = self.sample(*phase) {
+                    self.translate(current - pos);
+                }
+            }
+        }
+    }
+
+    /// Shift the whole trajectory without changing its shape
+    fn translate(&mut self, by: Vec3) {
+        match self {
+            Trajectory::Manual | Trajectory::Stationary | Trajectory::Path(_) => {}
+            Trajectory::Orbit { center, .. } | Trajectory::Lissajous { center, .. } => {
+                *center += by
+            }
+            Trajectory::Yoyo { start, end } => {
+                *start += by;
+                *end += by;
+            }
+        }
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct GhostMotion {
+    pub trajectory: Trajectory,
+    pub speed: f32, // phase per second: radians for parametric trajectories, 1.0 = real time for paths
+    #[reflect(ignore)]
+    phase: f64,
+    // which trajectory variant we last lined up with the ghost's position
+    #[reflect(ignore)]
+    anchored: Option<std::mem::Discriminant<Trajectory>>,
+}
+
+impl Default for GhostMotion {
+    fn default() -> Self {
+        Self {
+            trajectory: Trajectory::Manual,
+            speed: 1.0,
+            phase: 0.0,
+            anchored: None,
+        }
+    }
+}
+
+impl GhostMotion {
+    /// Jump to a point in the current trajectory without re-anchoring it
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase;
+        self.anchored = Some(std::mem::discriminant(&self.trajectory));
+    }
+}
+
 use std::f32::consts::PI;
-fn move_ghost(time: Res<Time>, mut query: Query<&mut Transform, With<Ghost>>) {
-    // move the sphere around
-    for mut t in query.iter_mut() {
-        let phase = time.elapsed_seconds() % PI;
-        t.translation = traj_orbit(phase.into(), Vec3::default(), 10.0);
-        //t.translation = traj_yoyo(phase.into(), Vec3{x: -10.0, y: 1.0, z: -10.0}, Vec3{x: 10.0, y: 1.0, z: 10.0})
+fn move_ghost(
+    time: Res<Time>,
+    bounds: Res<RoomBounds>,
+    mut query: Query<(&mut Transform, &mut GhostMotion, Has<Dragged>), With<Ghost>>,
+) {
+    for (mut t, mut motion, dragged) in query.iter_mut() {
+        // the trajectory waits while the ghost is held, so the drag doesn't fight it
+        if dragged {
+            continue;
+        }
+        let motion = &mut *motion;
+        let kind = std::mem::discriminant(&motion.trajectory);
+        if motion.anchored != Some(kind) {
+            // mode was switched: start the new trajectory where the ghost is now
+            motion.trajectory.anchor(&mut motion.phase, t.translation);
+            motion.anchored = Some(kind);
+        }
+
+        motion.phase += (time.delta_seconds() * motion.speed) as f64;
+        if let Some(pos) = motion.trajectory.sample(motion.phase) {
+            // trajectories are free to swing past the walls, the ghost stays in the room
+            t.translation = bounds.clamp(pos);
+        }
+    }
+}
+
+/// A dropped ghost picks its trajectory back up from where it was let go
+fn release_ghost(mut drag_end: EventReader<DragEnd>, mut ghosts: Query<&mut GhostMotion>) {
+    for e in drag_end.iter() {
+        if let Ok(mut motion) = ghosts.get_mut(e.entity) {
+            motion.anchored = None;
+        }
+    }
+}
+
+/// Stationary ghosts can't be dragged, every other trajectory can
+fn pin_stationary_ghosts(
+    mut commands: Commands,
+    ghosts: Query<(Entity, &GhostMotion, Has<Draggable>), Changed<GhostMotion>>,
+) {
+    for (entity, motion, draggable) in ghosts.iter() {
+        let pinned = motion.trajectory == Trajectory::Stationary;
+        match (pinned, draggable) {
+            (true, true) => {
+                commands.entity(entity).remove::<Draggable>();
+            }
+            (false, false) => {
+                commands.entity(entity).insert(Draggable);
+            }
+            _ => {}
+        }
     }
 }
 
-fn dim_by_distance(
-    ghost_query: Query<&GlobalTransform, With<Ghost>>,
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn dim_by_distance(
+    ghost_query: Query<(Entity, &GlobalTransform, &Ghost)>,
+    occluder: Occluder,
+    blend: Res<GhostBlend>,
+    falloff: Res<FalloffSettings>,
     intensity_bounds: Res<IntensityBounds>,
     distance_bounds: Res<DistanceBounds>,
-    bulb_state: ResMut<BulbState>,
-    mut light_query: Query<(&mut PointLight, &GlobalTransform, &Bulb)>,
+    bulb_state: Res<BulbState>,
+    mut light_query: Query<(&mut PointLight, &GlobalTransform, &Bulb, &Parent)>,
+    shade_query: Query<&Handle<StandardMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let ghost = ghost_query.single();
-    for (mut light, transform, bulb) in light_query.iter_mut() {
-        let d = ghost.translation().distance(transform.translation());
-        let mapped_game = d.map(
-            (distance_bounds.min, distance_bounds.max),
-            (intensity_bounds.max, intensity_bounds.min), // swapped around because highest
-                                                          // distance = lowest intensity
-        );
-        light.intensity = mapped_game;
-        let mapped_irl = d.map((distance_bounds.min, distance_bounds.max), (1.0, 0.0));
+    let any_colored = ghost_query.iter().any(|(_, _, g)| g.color.is_some());
+    for (mut light, transform, bulb, parent) in light_query.iter_mut() {
+        let seen: Vec<_> = ghost_query
+            .iter()
+            .filter_map(|(entity, ghost_transform, ghost)| {
+                let seen = occluder.seen(
+                    entity,
+                    ghost_transform.translation(),
+                    transform.translation(),
+                )?;
+                Some((ghost, seen))
+            })
+            .collect();
+        let influences = |curve: &Falloff| -> Vec<Influence> {
+            seen.iter()
+                .map(|(ghost, seen)| Influence {
+                    strength: curve.eval(seen.distance, (distance_bounds.min, distance_bounds.max))
+                        * seen.factor,
+                    distance: seen.distance,
+                    weight: ghost.weight,
+                })
+                .collect()
+        };
+        let real = blend.combine(&influences(&falloff.real_curve));
+        let influences = influences(&falloff.virtual_curve);
+        let strength = blend.combine(&influences);
+
+        light.intensity = strength.map((0.0, 1.0), (intensity_bounds.min, intensity_bounds.max));
+        bulb_state.set_brightness(bulb.index, real.into());
 
-        bulb_state.set_brightness(bulb.index, mapped_irl.into());
+        let tint = bulb.tint(
+            seen.iter()
+                .zip(&influences)
+                .map(|((ghost, _), influence)| (ghost.color, influence.strength)),
+            any_colored,
+        );
+        let color = tint.unwrap_or(bulb.color);
+        if light.color != color {
+            light.color = color;
+            bulb_state.set_hue(bulb.index, bulb.hue(tint));
+            // the shade glows in the same color
+            if let Some(material) = shade_query
+                .get(parent.get())
+                .ok()
+                .and_then(|h| materials.get_mut(h))
+            {
+                material.base_color = color;
+                material.emissive = color;
+            }
+        }
     }
 }
 
@@ -103,6 +413,7 @@ fn spawn_ghost(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    layout: Res<Layout>,
 ) {
     let mesh = meshes.add(Mesh::from(shape::UVSphere {
         radius: 1.0,
@@ -119,6 +430,10 @@ fn spawn_ghost(
     });
 
     let material = materials.add(Color::rgb(0.7, 0.7, 0.7).into());
+    commands.insert_resource(GhostAssets {
+        mesh: mesh.clone(),
+        material: material.clone(),
+    });
     commands
         .spawn(PbrBundle {
             mesh,
@@ -126,7 +441,51 @@ fn spawn_ghost(
             transform: Transform::from_xyz(0.0, 1.0, 0.0),
             ..Default::default()
         })
-        .insert(Ghost)
+        .insert(Ghost::default())
+        .insert(GhostMotion {
+            trajectory: match &layout.ghost_path {
+                Some(path) => Trajectory::Path(path.clone()),
+                None => Trajectory::Manual,
+            },
+            ..default()
+        })
+        .insert(Hoverable)
+        .insert(Draggable);
+}
+
+#[derive(Resource)]
+struct GhostAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// G adds another ghost next to the first one, Shift+G removes the newest
+fn add_remove_ghosts(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    assets: Res<GhostAssets>,
+    ghosts: Query<Entity, With<Ghost>>,
+) {
+    if !keys.just_pressed(KeyCode::G) {
+        return;
+    }
+    let count = ghosts.iter().count();
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        // keep at least one around
+        if let Some(newest) = ghosts.iter().max().filter(|_| count > 1) {
+            commands.entity(newest).despawn_recursive();
+        }
+        return;
+    }
+    commands
+        .spawn(PbrBundle {
+            mesh: assets.mesh.clone(),
+            material: assets.material.clone(),
+            transform: Transform::from_xyz(-3.0 * count as f32, 1.0, 0.0),
+            ..Default::default()
+        })
+        .insert(Ghost::default())
+        .insert(GhostMotion::default())
         .insert(Hoverable)
         .insert(Draggable);
 }
@@ -138,6 +497,7 @@ fn spawn_lights(
     spawned_bulbs: Query<&Bulb>,
     asset_server: Res<AssetServer>,
     bulb_state: Res<BulbState>,
+    layout: Res<Layout>,
 ) {
     if !bulb_state.ready() {
         println!("bulb_state not ready");
@@ -157,16 +517,21 @@ fn spawn_lights(
     //dbg!(&spawned_indicies);
 
     for (i, bulb) in bulbs.iter().filter(|b| !spawned_indicies.contains(&b.idx)).enumerate() {
-        let light_color = Color::hsla(bulb.hue as f32, 1f32, 0.5f32, 1f32);
+        // the bridge's hue is 0-1, `hsla` wants degrees
+        let light_color = Color::hsla(bulb.hue as f32 * 360.0, 1f32, 0.5f32, 1f32);
+        let transform = layout
+            .lamp_transform(bulb.idx)
+            .unwrap_or(Transform::from_xyz(0.0, 0.0, i as f32));
         commands
             .spawn(
                 PbrBundle {
                     mesh: light_mesh_stand.clone(), // stand
                     material: light_material_stand.clone(),
-                    transform: Transform::from_xyz(0.0, 0.0, i as f32),
+                    transform,
                     ..default()
                 },
             )
+            .insert(Lamp { index: bulb.idx })
             .insert(hover::Draggable {})
             .insert(hover::Hoverable {})
             .with_children(|builder| {
@@ -192,7 +557,11 @@ fn spawn_lights(
                                     },
                                     ..default()
                                 },
-                                bulb: bulb::Bulb { index: bulb.idx },
+                                bulb: bulb::Bulb {
+                                    index: bulb.idx,
+                                    color: light_color,
+                                    hue: bulb.hue,
+                                },
                             })
                             .insert(SpatialBundle {
                                 transform: Transform::from_xyz(0.0, 8.0, 0.0),
@@ -208,10 +577,159 @@ pub struct BulbPlugin;
 impl Plugin for BulbPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(crate::hue::HuePlugin {})
+            .add_plugins(crate::falloff::FalloffPlugin)
+            .register_type::<GhostMotion>()
+            .register_type::<Trajectory>()
+            .register_type::<Ghost>()
+            .register_type::<GhostBlend>()
+            .init_resource::<GhostBlend>()
             .add_systems(Startup, spawn_ghost)
-            //.add_systems(Update, move_ghost)
+            .add_systems(Update, move_ghost)
+            .add_systems(Update, add_remove_ghosts.run_if(not(egui_wants_keyboard)))
+            .add_systems(Update, release_ghost.before(move_ghost))
+            .add_systems(Update, pin_stationary_ghosts)
             .add_systems(Update, spawn_lights)
-            .add_systems(Update, dim_by_distance);
-                
+            .add_systems(
+                Update,
+                dim_by_distance
+                    .after(move_ghost)
+                    .after(crate::occlusion::update_fields)
+                    .after(crate::falloff::sort_curves),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pull(strength: f32, distance: f32, weight: f32) -> Influence {
+        Influence {
+            strength,
+            distance,
+            weight,
+        }
+    }
+
+    #[test]
+    fn blends_combine_ghosts() {
+        let both = [pull(0.5, 4.0, 1.0), pull(0.8, 1.0, 1.0)];
+        assert_eq!(GhostBlend::Max.combine(&both), 0.8);
+        // the sum would be 1.3
+        assert_eq!(GhostBlend::SumClamped.combine(&both), 1.0);
+        assert_eq!(
+            GhostBlend::SumClamped.combine(&[pull(0.2, 1.0, 1.0), pull(0.5, 4.0, 1.0)]),
+            0.7
+        );
+        // the closest ghost wins even when a further one pulls harder
+        assert_eq!(
+            GhostBlend::Nearest.combine(&[pull(0.9, 5.0, 1.0), pull(0.1, 2.0, 1.0)]),
+            0.1
+        );
+        // 1/1 and 1/4 as weights: (0.8 + 0.5 / 4) / 1.25
+        let average = GhostBlend::WeightedAverage.combine(&both);
+        assert!((average - 0.74).abs() < 1e-5);
+        // a heavier far ghost pulls the average toward itself
+        let heavy =
+            GhostBlend::WeightedAverage.combine(&[pull(0.8, 1.0, 1.0), pull(0.5, 4.0, 4.0)]);
+        assert!((heavy - 0.65).abs() < 1e-5);
+    }
+
+    #[test]
+    fn blends_without_ghosts() {
+        for blend in [
+            GhostBlend::Max,
+            GhostBlend::SumClamped,
+            GhostBlend::Nearest,
+            GhostBlend::WeightedAverage,
+        ] {
+            assert_eq!(blend.combine(&[]), 0.0);
+        }
+        // ghosts that weigh nothing don't count
+        let weightless = [pull(0.8, 1.0, 0.0), pull(0.5, 2.0, 0.0)];
+        assert_eq!(GhostBlend::WeightedAverage.combine(&weightless), 0.0);
+        // a ghost right on top of the bulb doesn't divide by zero
+        let on_top =
+            GhostBlend::WeightedAverage.combine(&[pull(1.0, 0.0, 1.0), pull(0.2, 3.0, 1.0)]);
+        assert!((on_top - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tint_mixes_by_pull() {
+        let bulb = bulb(0.0);
+        let (red, blue) = (Some(Color::RED), Some(Color::BLUE));
+        let channels = |c: Color| c.as_linear_rgba_f32();
+        // equal pulls meet in the middle
+        let even = bulb
+            .tint([(red, 0.5), (blue, 0.5)].into_iter(), true)
+            .unwrap();
+        let [r, g, b, a] = channels(even);
+        assert!((r - 0.5).abs() < 1e-5 && g.abs() < 1e-5 && (b - 0.5).abs() < 1e-5);
+        assert_eq!(a, 1.0);
+        // the stronger pull dominates, scaling every pull the same changes nothing
+        let strong = channels(
+            bulb.tint([(red, 0.9), (blue, 0.1)].into_iter(), true)
+                .unwrap(),
+        );
+        let scaled = channels(
+            bulb.tint([(red, 0.45), (blue, 0.05)].into_iter(), true)
+                .unwrap(),
+        );
+        assert!(strong[0] > 0.85 && strong[2] < 0.15);
+        assert!(strong.iter().zip(scaled).all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn still_trajectories_stay_put() {
+        for trajectory in [Trajectory::Manual, Trajectory::Stationary] {
+            assert_eq!(trajectory.sample(1.0), None);
+        }
+        let orbit = Trajectory::Orbit {
+            center: Vec3::ZERO,
+            radius: 2.0,
+        };
+        assert!(orbit.sample(0.0).unwrap().abs_diff_eq(Vec3::X * 2.0, 1e-5));
+    }
+
+    #[test]
+    fn anchoring_moves_the_trajectory_not_the_ghost() {
+        let mut yoyo = Trajectory::Yoyo {
+            start: Vec3::ZERO,
+            end: Vec3::X * 4.0,
+        };
+        let mut phase = 0.0;
+        let ghost = Vec3::new(1.0, 2.0, 3.0);
+        yoyo.anchor(&mut phase, ghost);
+        // picks up right where the ghost is, with the same shape
+        assert!(yoyo.sample(phase).unwrap().abs_diff_eq(ghost, 1e-5));
+        let Trajectory::Yoyo { start, end } = yoyo else {
+            unreachable!()
+        };
+        assert!((end - start).abs_diff_eq(Vec3::X * 4.0, 1e-5));
+    }
+
+    fn bulb(hue: f64) -> Bulb {
+        Bulb {
+            index: 1,
+            color: Color::hsla(hue as f32 * 360.0, 1.0, 0.5, 1.0),
+            hue,
+        }
+    }
+
+    #[test]
+    fn bulb_returns_to_its_read_hue() {
+        let bulb = bulb(0.61);
+        let blue = Some(Color::BLUE);
+        // a colored ghost tints it
+        let tint = bulb.tint([(blue, 1.0)].into_iter(), true);
+        assert!((bulb.hue(tint) - 240.0 / 360.0).abs() < 1e-3);
+        // an uncolored ghost next to a colored one only pulls part of the way back
+        let mixed = bulb.tint([(None, 1.0), (blue, 1.0)].into_iter(), true);
+        assert!(mixed.is_some() && mixed != tint);
+        // once the colored ghost has gone, the bridge gets exactly the hue it gave us
+        assert_eq!(bulb.tint([(None, 1.0)].into_iter(), false), None);
+        assert_eq!(bulb.hue(None), 0.61);
+        // and a colored ghost too far away to pull doesn't count either
+        assert_eq!(bulb.tint([(blue, 0.0)].into_iter(), true), None);
     }
 }
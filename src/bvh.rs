@@ -0,0 +1,383 @@
+// Bounding volume hierarchy over a mesh's triangles, so ray picking doesn't test every triangle
+use crate::hover::moller_trumbore;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+const LEAF_SIZE: usize = 4;
+
+struct Node {
+    min: Vec3,
+    max: Vec3,
+    // leaf: triangles[start..start + count], interior (count == 0): children at nodes[start], nodes[start + 1]
+    start: u32,
+    count: u32,
+}
+
+/// Closest triangle hit by a ray, `t` in units of the ray direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BvhHit {
+    pub t: f32,
+    pub triangle: [Vec3; 3],
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<[Vec3; 3]>, // object space, reordered so every leaf is a contiguous run
+}
+
+impl Bvh {
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.iter().map(|i| *i as usize).collect(),
+            Some(Indices::U16(indices)) => indices.iter().map(|i| *i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let triangles = indices
+            .chunks_exact(3)
+            .filter(|tri| tri.iter().all(|i| *i < positions.len()))
+            .map(|tri| [tri[0], tri[1], tri[2]].map(|i| Vec3::from(positions[i])))
+            .collect();
+        Some(Self::from_triangles(triangles))
+    }
+
+    pub fn from_triangles(mut triangles: Vec<[Vec3; 3]>) -> Self {
+        let mut nodes = vec![Node {
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+            start: 0,
+            count: triangles.len() as u32,
+        }];
+        build(&mut nodes, 0, &mut triangles);
+        Self { nodes, triangles }
+    }
+
+    /// Closest hit along the ray, in the same space the BVH was built in
+    pub fn intersect(&self, origin: Vec3, direction: Vec3) -> Option<BvhHit> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let inv_dir = direction.recip();
+        let mut closest: Option<BvhHit> = None;
+        let mut stack = vec![0usize];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            let limit = closest.map_or(f32::INFINITY, |h| h.t);
+            match slab(origin, inv_dir, node.min, node.max) {
+                Some(t) if t <= limit => {}
+                _ => continue,
+            }
+            if node.count > 0 {
+                let (start, end) = (node.start as usize, (node.start + node.count) as usize);
+                for tri in &self.triangles[start..end] {
+                    if let Some(t) = moller_trumbore(origin, direction, tri[0], tri[1], tri[2]) {
+                        if closest.is_none_or(|h| t < h.t) {
+                            closest = Some(BvhHit { t, triangle: *tri });
+                        }
+                    }
+                }
+            } else {
+                stack.push(node.start as usize);
+                stack.push(node.start as usize + 1);
+            }
+        }
+        closest
+    }
+
+    /// Closest hit for a world space ray, testing in object space so no vertex gets transformed
+    pub fn intersect_world(&self, ray: Ray, transform: &GlobalTransform) -> Option<BvhHit> {
+        let to_object = transform.compute_matrix().inverse();
+        // direction is not renormalized, so `t` stays a world space distance
+        let origin = to_object.transform_point3(ray.origin);
+        let direction = to_object.transform_vector3(ray.direction);
+        self.intersect(origin, direction)
+    }
+}
+
+fn bounds(triangles: &[[Vec3; 3]]) -> (Vec3, Vec3) {
+    triangles.iter().flatten().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), v| (min.min(*v), max.max(*v)),
+    )
+}
+
+fn centroid(tri: &[Vec3; 3]) -> Vec3 {
+    (tri[0] + tri[1] + tri[2]) / 3.0
+}
+
+fn build(nodes: &mut Vec<Node>, i: usize, triangles: &mut [[Vec3; 3]]) {
+    let (start, count) = (nodes[i].start as usize, nodes[i].count as usize);
+    let tris = &mut triangles[start..start + count];
+    (nodes[i].min, nodes[i].max) = bounds(tris);
+    if count <= LEAF_SIZE {
+        return;
+    }
+
+    // split at the median centroid along the longest axis
+    let (cmin, cmax) = tris.iter().map(centroid).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), c| (min.min(c), max.max(c)),
+    );
+    let extent = cmax - cmin;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = count / 2;
+    tris.select_nth_unstable_by(mid, |a, b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+
+    let left = nodes.len();
+    nodes.push(Node {
+        min: Vec3::ZERO,
+        max: Vec3::ZERO,
+        start: start as u32,
+        count: mid as u32,
+    });
+    nodes.push(Node {
+        min: Vec3::ZERO,
+        max: Vec3::ZERO,
+        start: (start + mid) as u32,
+        count: (count - mid) as u32,
+    });
+    nodes[i].start = left as u32;
+    nodes[i].count = 0;
+    build(nodes, left, triangles);
+    build(nodes, left + 1, triangles);
+}
+
+/// Entry distance of the ray into the box, if it hits it at all
+fn slab(origin: Vec3, inv_dir: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let t1 = (min - origin) * inv_dir;
+    let t2 = (max - origin) * inv_dir;
+    // NaN from 0 * inf (ray on a slab plane) is dropped by min/max
+    let t_near = t1.min(t2).max_element().max(0.0);
+    let t_far = t1.max(t2).min_element();
+    (t_near <= t_far).then_some(t_near)
+}
+
+/// FNV-1a, the same on every build and platform unlike `DefaultHasher`, for keys kept on disk
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl std::hash::Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // fixed width and byte order, whatever the platform's are
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hash of a mesh's positions and indices, same on every build and platform.
+/// Tells shape changes apart from edits that only touch colors or other attributes.
+pub fn geometry_hash(mesh: &Mesh) -> u64 {
+    let mut hasher = StableHasher::default();
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    {
+        positions
+            .iter()
+            .flatten()
+            .for_each(|v| hasher.write_u32(v.to_bits()));
+    }
+    // between positions and indices
+    hasher.write_u32(u32::MAX);
+    match mesh.indices() {
+        Some(Indices::U32(indices)) => indices.iter().for_each(|i| hasher.write_u32(*i)),
+        Some(Indices::U16(indices)) => indices.iter().for_each(|i| hasher.write_u32(*i as u32)),
+        None => {}
+    }
+    hasher.finish()
+}
+
+/// BVHs for every loaded mesh, kept in sync with `Assets<Mesh>`
+#[derive(Resource, Default)]
+pub struct MeshBvhs(HashMap<Handle<Mesh>, (u64, Arc<Bvh>)>); // with the geometry hash it was built from
+
+impl MeshBvhs {
+    /// Shared, so background tasks can cast rays while the mesh is rebuilt
+    pub fn get(&self, handle: &Handle<Mesh>) -> Option<&Arc<Bvh>> {
+        self.0.get(handle).map(|(_, bvh)| bvh)
+    }
+
+    /// A BVH built elsewhere, like in a background task, for a mesh with this geometry hash
+    pub fn insert(&mut self, handle: &Handle<Mesh>, hash: u64, bvh: Bvh) {
+        self.0.insert(handle.clone_weak(), (hash, Arc::new(bvh)));
+    }
+
+    /// [`geometry_hash`] of the mesh the BVH was built from
+    pub fn geometry(&self, handle: &Handle<Mesh>) -> Option<u64> {
+        self.0.get(handle).map(|(hash, _)| *hash)
+    }
+}
+
+fn update_bvhs(
+    mut events: EventReader<AssetEvent<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    mut bvhs: ResMut<MeshBvhs>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let Some(mesh) = meshes.get(handle) else {
+                    bvhs.0.remove(handle);
+                    continue;
+                };
+                // recoloring a mesh doesn't move any triangles
+                let hash = geometry_hash(mesh);
+                if bvhs.0.get(handle).is_some_and(|(built, _)| *built == hash) {
+                    continue;
+                }
+                match Bvh::from_mesh(mesh) {
+                    Some(bvh) => bvhs.0.insert(handle.clone_weak(), (hash, Arc::new(bvh))),
+                    None => bvhs.0.remove(handle),
+                };
+            }
+            AssetEvent::Removed { handle } => {
+                bvhs.0.remove(handle);
+            }
+        }
+    }
+}
+
+pub struct BvhPlugin;
+
+impl Plugin for BvhPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MeshBvhs>()
+            .add_systems(PreUpdate, update_bvhs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // small deterministic generator so the test doesn't need `rand`
+    fn triangles(n: usize) -> Vec<[Vec3; 3]> {
+        let mut seed = 12345u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 20.0 - 10.0
+        };
+        (0..n)
+            .map(|_| {
+                let c = Vec3::new(next(), next(), next());
+                [
+                    c,
+                    c + Vec3::new(next(), next(), next()) * 0.1,
+                    c + Vec3::new(next(), next(), next()) * 0.1,
+                ]
+            })
+            .collect()
+    }
+
+    fn brute_force(tris: &[[Vec3; 3]], origin: Vec3, direction: Vec3) -> Option<f32> {
+        tris.iter()
+            .filter_map(|t| moller_trumbore(origin, direction, t[0], t[1], t[2]))
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let tris = triangles(500);
+        let bvh = Bvh::from_triangles(tris.clone());
+        let mut hits = 0;
+        for i in 0..400 {
+            let a = i as f32 * 0.37;
+            let origin = Vec3::new(a.cos() * 15.0, (a * 0.5).sin() * 5.0, a.sin() * 15.0);
+            let target = Vec3::new((a * 3.1).sin() * 8.0, (a * 1.3).cos() * 8.0, 0.0);
+            let direction = (target - origin).normalize();
+            let expected = brute_force(&tris, origin, direction);
+            let got = bvh.intersect(origin, direction).map(|h| h.t);
+            assert_eq!(expected, got);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 0, "rays should hit something");
+    }
+
+    #[test]
+    fn misses_outside_bounds() {
+        let bvh = Bvh::from_triangles(triangles(50));
+        let max = bvh.nodes[0].max;
+        assert!(bvh.intersect(max + Vec3::ONE, Vec3::Y).is_none());
+    }
+
+    #[test]
+    fn world_space_ray_matches_scaled_mesh() {
+        let tri = [
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let bvh = Bvh::from_triangles(vec![tri]);
+        let transform = GlobalTransform::from(
+            Transform::from_scale(Vec3::splat(5.0)).with_translation(Vec3::new(0.0, -1.0, 0.0)),
+        );
+        let ray = Ray {
+            origin: Vec3::new(0.0, 10.0, 0.0),
+            direction: Vec3::NEG_Y,
+        };
+        let hit = bvh.intersect_world(ray, &transform).unwrap();
+        assert!((hit.t - 11.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn geometry_hash_ignores_colors() {
+        use bevy::render::render_resource::PrimitiveTopology;
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        );
+        let hash = geometry_hash(&mesh);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1.0, 0.0, 0.0, 1.0]; 3]);
+        assert_eq!(geometry_hash(&mesh), hash);
+        mesh.set_indices(Some(Indices::U32(vec![0, 2, 1])));
+        assert_ne!(geometry_hash(&mesh), hash);
+        // and so does moving a vertex
+        let mut moved = mesh.clone();
+        moved.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        );
+        assert_ne!(geometry_hash(&moved), geometry_hash(&mesh));
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let bvh = Bvh::from_triangles(vec![]);
+        assert!(bvh.intersect(Vec3::ZERO, Vec3::X).is_none());
+    }
+}
@@ -0,0 +1,370 @@
+// Orbit, pan and zoom around a focus point. Left button belongs to picking and dragging, so the
+// camera uses the right button (orbit), middle or Shift+right (pan) and the wheel (zoom).
+use crate::bulb::Lamp;
+use crate::hover::{egui_wants_keyboard, DoubleClick};
+use crate::layout::Layout;
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_8};
+
+const ORBIT_SPEED: f32 = 0.005; // radians per pixel
+const PAN_SPEED: f32 = 0.0015; // of the distance, per pixel
+const ZOOM_STEP: f32 = 0.9; // distance factor per wheel line
+const FOCUS_DISTANCE: f32 = 10.0; // closest a double click zooms in
+const MIN_PITCH: f32 = -1.54; // just short of straight down, where yaw stops making sense
+const MAX_PITCH: f32 = -0.05;
+
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq)]
+#[reflect(Component)]
+pub struct OrbitCamera {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32, // negative looks down
+    pub distance: f32,
+    pub top_down: bool, // straight down with an orthographic projection
+    goal: Option<Vec3>, // focus glides here after a double click
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self {
+            focus: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: -0.6,
+            distance: 40.0,
+            top_down: false,
+            goal: None,
+        }
+    }
+}
+
+impl OrbitCamera {
+    /// Orbit around the point on the floor the camera is looking at
+    pub fn from_transform(transform: &Transform) -> Self {
+        let forward = transform.forward();
+        let focus = if forward.y < -f32::EPSILON {
+            transform.translation + forward * (-transform.translation.y / forward.y)
+        } else {
+            transform.translation + forward * 10.0
+        };
+        let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+        Self {
+            focus,
+            yaw,
+            pitch: pitch.clamp(MIN_PITCH, MAX_PITCH),
+            distance: transform.translation.distance(focus),
+            ..default()
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        let pitch = if self.top_down {
+            -FRAC_PI_2
+        } else {
+            self.pitch
+        };
+        let rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, pitch, 0.0);
+        Transform::from_translation(self.focus + rotation * Vec3::Z * self.distance)
+            .with_rotation(rotation)
+    }
+
+    /// Height of the view at the focus, so switching projections keeps things the same size
+    fn view_height(&self) -> f32 {
+        2.0 * self.distance * FRAC_PI_8.tan()
+    }
+
+    fn orbit(&mut self, delta: Vec2) {
+        self.yaw -= delta.x * ORBIT_SPEED;
+        self.pitch = (self.pitch - delta.y * ORBIT_SPEED).clamp(MIN_PITCH, MAX_PITCH);
+    }
+
+    /// Drags the floor along with the cursor
+    fn pan(&mut self, delta: Vec2) {
+        let rotation = Quat::from_rotation_y(self.yaw);
+        let (right, back) = (rotation * Vec3::X, rotation * Vec3::Z);
+        self.focus += (-right * delta.x - back * delta.y) * self.distance * PAN_SPEED;
+        self.goal = None;
+    }
+
+    fn zoom(&mut self, lines: f32) {
+        self.distance = (self.distance * ZOOM_STEP.powf(lines)).clamp(1.0, 200.0);
+    }
+
+    fn view(&self, name: String) -> SavedView {
+        SavedView {
+            name,
+            focus: self.focus,
+            yaw: self.yaw,
+            pitch: self.pitch,
+            distance: self.distance,
+            top_down: self.top_down,
+        }
+    }
+
+    fn go_to(&mut self, view: &SavedView) {
+        *self = Self {
+            focus: view.focus,
+            yaw: view.yaw,
+            pitch: view.pitch,
+            distance: view.distance,
+            top_down: view.top_down,
+            goal: None,
+        };
+    }
+}
+
+/// A named viewpoint, kept with the layout
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedView {
+    pub name: String,
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    #[serde(default)]
+    pub top_down: bool,
+}
+
+fn orbit_controls(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut contexts: EguiContexts,
+    mut cameras: Query<&mut OrbitCamera>,
+) {
+    let delta: Vec2 = motion.iter().map(|m| m.delta).sum();
+    let lines: f32 = wheel
+        .iter()
+        .map(|w| match w.unit {
+            MouseScrollUnit::Line => w.y,
+            MouseScrollUnit::Pixel => w.y / 100.0,
+        })
+        .sum();
+    if contexts.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    for mut camera in cameras.iter_mut() {
+        if mouse.pressed(MouseButton::Middle) || (shift && mouse.pressed(MouseButton::Right)) {
+            camera.pan(delta);
+        } else if mouse.pressed(MouseButton::Right) {
+            camera.orbit(delta);
+        }
+        if lines != 0.0 {
+            camera.zoom(lines);
+        }
+    }
+}
+
+/// Double-clicking a lamp brings it to the middle of the screen
+fn focus_on_lamp(
+    mut double_clicks: EventReader<DoubleClick>,
+    lamps: Query<&GlobalTransform, With<Lamp>>,
+    mut cameras: Query<&mut OrbitCamera>,
+) {
+    for event in double_clicks.iter() {
+        let (MouseButton::Left, Ok(lamp)) = (event.button, lamps.get(event.entity)) else {
+            continue;
+        };
+        for mut camera in cameras.iter_mut() {
+            camera.goal = Some(lamp.translation());
+            camera.distance = camera.distance.min(FOCUS_DISTANCE);
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct ViewsWindow {
+    open: bool,
+    name: String,
+}
+
+/// T switches to the top-down plan view, 1-9 jump to saved views, Ctrl+B lists them
+fn camera_keys(
+    keys: Res<Input<KeyCode>>,
+    layout: Res<Layout>,
+    mut window: ResMut<ViewsWindow>,
+    mut cameras: Query<&mut OrbitCamera>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl && keys.just_pressed(KeyCode::B) {
+        window.open = !window.open;
+    }
+    let digits = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let view = digits
+        .iter()
+        .position(|key| keys.just_pressed(*key))
+        .and_then(|i| layout.views.get(i));
+    for mut camera in cameras.iter_mut() {
+        if keys.just_pressed(KeyCode::T) {
+            camera.top_down = !camera.top_down;
+        }
+        if let Some(view) = view {
+            camera.go_to(view);
+        }
+    }
+}
+
+fn views_window(
+    mut window: ResMut<ViewsWindow>,
+    mut layout: ResMut<Layout>,
+    mut contexts: EguiContexts,
+    mut cameras: Query<&mut OrbitCamera>,
+) {
+    if !window.open {
+        return;
+    }
+    let Ok(mut camera) = cameras.get_single_mut() else {
+        return;
+    };
+    let window = &mut *window;
+    let mut remove = None;
+    egui::Window::new("Views")
+        .open(&mut window.open)
+        .show(contexts.ctx_mut(), |ui| {
+            for (i, view) in layout.views.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button(format!("{} {}", i + 1, view.name)).clicked() {
+                        camera.go_to(view);
+                    }
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut window.name);
+                if ui.button("Save view").clicked() && !window.name.is_empty() {
+                    let view = camera.view(std::mem::take(&mut window.name));
+                    match layout.views.iter_mut().find(|v| v.name == view.name) {
+                        Some(existing) => *existing = view,
+                        None => layout.views.push(view),
+                    }
+                }
+            });
+            ui.label("Ctrl+S saves them with the layout");
+        });
+    if let Some(i) = remove {
+        layout.views.remove(i);
+    }
+}
+
+fn apply_orbit(
+    time: Res<Time>,
+    mut cameras: Query<(&mut OrbitCamera, &mut Transform, &mut Projection)>,
+) {
+    for (mut camera, mut transform, mut projection) in cameras.iter_mut() {
+        if let Some(goal) = camera.goal {
+            let t = 1.0 - (-10.0 * time.delta_seconds()).exp();
+            camera.focus = camera.focus.lerp(goal, t);
+            if camera.focus.distance(goal) < 0.01 {
+                camera.goal = None;
+            }
+        }
+        *transform = camera.transform();
+        match (&mut *projection, camera.top_down) {
+            (Projection::Orthographic(ortho), true) => {
+                ortho.scaling_mode = ScalingMode::FixedVertical(camera.view_height());
+            }
+            (Projection::Perspective(_), true) => {
+                *projection = Projection::Orthographic(OrthographicProjection {
+                    scaling_mode: ScalingMode::FixedVertical(camera.view_height()),
+                    ..default()
+                });
+            }
+            (Projection::Orthographic(_), false) => {
+                *projection = Projection::Perspective(default());
+            }
+            (Projection::Perspective(_), false) => {}
+        }
+    }
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<OrbitCamera>()
+            .init_resource::<ViewsWindow>()
+            .add_systems(
+                Update,
+                (
+                    orbit_controls,
+                    focus_on_lamp,
+                    camera_keys.run_if(not(egui_wants_keyboard)),
+                    views_window,
+                ),
+            )
+            .add_systems(
+                Update,
+                apply_orbit
+                    .after(orbit_controls)
+                    .after(focus_on_lamp)
+                    .after(camera_keys),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbit_round_trip() {
+        let camera = OrbitCamera {
+            focus: Vec3::new(1.0, 0.0, -2.0),
+            yaw: 0.8,
+            pitch: -0.5,
+            distance: 20.0,
+            ..default()
+        };
+        let transform = camera.transform();
+        // looks at the focus from `distance` away
+        let to_focus = (camera.focus - transform.translation).normalize();
+        assert!(to_focus.distance(transform.forward()) < 1e-5);
+        assert!((transform.translation.distance(camera.focus) - 20.0).abs() < 1e-4);
+        // and can be recovered from the transform
+        let back = OrbitCamera::from_transform(&transform);
+        assert!(back.focus.distance(camera.focus) < 1e-3);
+        assert!((back.yaw - camera.yaw).abs() < 1e-4);
+        assert!((back.pitch - camera.pitch).abs() < 1e-4);
+    }
+
+    #[test]
+    fn top_down_looks_straight_down() {
+        let camera = OrbitCamera {
+            top_down: true,
+            ..default()
+        };
+        let transform = camera.transform();
+        assert!(transform.forward().distance(Vec3::NEG_Y) < 1e-5);
+        assert!((transform.translation.y - camera.distance).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pan_follows_the_cursor() {
+        let mut camera = OrbitCamera::default();
+        // dragging right moves the floor right, so the focus goes left
+        camera.pan(Vec2::new(100.0, 0.0));
+        assert!(camera.focus.x < 0.0);
+        assert_eq!(camera.focus.y, 0.0);
+        // zoom stays in range
+        camera.zoom(1000.0);
+        assert_eq!(camera.distance, 1.0);
+    }
+}
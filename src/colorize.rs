@@ -1,82 +1,487 @@
+// Bakes a vertex color gradient into meshes under a `Colorize` entity, so untextured rooms get
+// some depth. Rooms opt in when they're spawned; glTF nodes can opt in with
+// `"extras": {"colorize": {"mode": "height", "ramp": ["#303040", "#e0e0d0"]}}` or just `true`.
+use crate::ao::{self, AmbientOcclusion};
+use crate::bvh::MeshBvhs;
+use crate::cutaway::CutawayMesh;
+use crate::hover::egui_wants_keyboard;
+use crate::preview::{light_preview, preview_keys, LightPreview, Lighting};
+use crate::room::{RoomGeometry, RoomMesh};
+use crate::util::MapRange;
+use bevy::ecs::query::Has;
+use bevy::gltf::GltfExtras;
 use bevy::prelude::*;
-use bevy::render::mesh::VertexAttributeValues;
-use std::collections::HashSet;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-#[derive(Resource)]
-struct Meshes {
-    pub inner: HashSet<Handle<Mesh>>,
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorizeMode {
+    Height, // along the mesh's up axis
+    #[default]
+    Diagonal, // along `Colorize::direction`
+    Normal, // shaded by how much each face turns toward `Colorize::direction`
 }
 
-fn check_mesh_loaded(
-    mut commands: Commands,
-    mut events: EventReader<AssetEvent<Mesh>>,
-    mesh_handles: Res<Meshes>,
-    mut meshes: ResMut<Assets<Mesh>>,
-) {
-    for event in events.iter() {
-        match event {
-            AssetEvent::Created { handle } if mesh_handles.inner.contains(handle) => {
-                if let Some(mesh) = meshes.get_mut(handle) {
-                    println!("Mesh {handle:?} loaded, applying vertex color");
-                    if let Some(VertexAttributeValues::Float32x3(positions)) =
-                        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
-                    {
-                        let (mut min_x, mut min_y, mut min_z) =
-                            (f32::INFINITY, f32::INFINITY, f32::INFINITY);
-                        let (mut max_x, mut max_y, mut max_z) =
-                            (-f32::INFINITY, -f32::INFINITY, -f32::INFINITY);
-                        for [x, y, z] in positions {
-                            min_x = min_x.min(*x);
-                            min_y = min_y.min(*y);
-                            min_z = min_z.min(*z);
-                            max_x = max_x.max(*x);
-                            max_y = max_y.max(*y);
-                            max_z = max_z.max(*z);
-                        }
-                        println!(
-                            "Mesh boundaries: [{min_x}:{max_x},{min_y}:{max_y},{min_z}:{max_z}]"
-                        );
-
-                        use crate::util::MapRange;
-                        let colors: Vec<[f32; 4]> = positions
-                            .iter()
-                            .map(|[x, y, z]| {
-                                let mapped = (x + y + z).map(
-                                    (min_x + min_y + min_z, max_x + max_y + max_z),
-                                    (0.3, 0.9),
-                                );
-                                [mapped, mapped, mapped, 1.0]
-                            })
-                            .collect();
-                        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
-                    }
+/// Colors every mesh below this entity, unless a closer ancestor has its own settings
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[reflect(Component)]
+pub struct Colorize {
+    pub mode: ColorizeMode,
+    pub direction: Vec3,
+    pub ramp: Vec<Color>, // evenly spaced stops from low to high
+    pub occlusion: Option<AmbientOcclusion>, // opt-in, baked in the background once the mesh has loaded
+}
+
+impl Default for Colorize {
+    fn default() -> Self {
+        Self {
+            mode: ColorizeMode::Diagonal,
+            direction: Vec3::ONE,
+            ramp: vec![
+                Color::rgb_linear(0.3, 0.3, 0.3),
+                Color::rgb_linear(0.9, 0.9, 0.9),
+            ],
+            occlusion: None,
+        }
+    }
+}
+
+impl Colorize {
+    /// Settings from a glTF node's extras, `None` when it doesn't ask to be colorized
+    fn from_extras(extras: &str) -> Option<Self> {
+        let extras: serde_json::Value = serde_json::from_str(extras).ok()?;
+        let settings = extras.get("colorize")?;
+        let mut colorize = Self::default();
+        match settings {
+            serde_json::Value::Bool(true) => return Some(colorize),
+            serde_json::Value::Object(_) => {}
+            _ => return None,
+        }
+        match settings.get("mode").and_then(|m| m.as_str()) {
+            Some("height") => colorize.mode = ColorizeMode::Height,
+            Some("diagonal") => colorize.mode = ColorizeMode::Diagonal,
+            Some("normal") => colorize.mode = ColorizeMode::Normal,
+            Some(other) => eprintln!("unknown colorize mode {other}"),
+            None => {}
+        }
+        if let Some(direction) = settings.get("direction").and_then(|d| d.as_array()) {
+            let d: Vec<f32> = direction
+                .iter()
+                .filter_map(|v| v.as_f64())
+                .map(|v| v as f32)
+                .collect();
+            if let [x, y, z] = d[..] {
+                colorize.direction = Vec3::new(x, y, z);
+            }
+        }
+        if let Some(ramp) = settings.get("ramp").and_then(|r| r.as_array()) {
+            let ramp: Vec<Color> = ramp
+                .iter()
+                .filter_map(|c| Color::hex(c.as_str()?).ok())
+                .collect();
+            if !ramp.is_empty() {
+                colorize.ramp = ramp;
+            }
+        }
+        match settings.get("occlusion") {
+            Some(serde_json::Value::Bool(true)) => {
+                colorize.occlusion = Some(AmbientOcclusion::default())
+            }
+            Some(occlusion @ serde_json::Value::Object(_)) => {
+                let mut ao = AmbientOcclusion::default();
+                let number = |key: &str| occlusion.get(key).and_then(|v| v.as_f64());
+                if let Some(rays) = number("rays") {
+                    ao.rays = rays as u32;
+                }
+                if let Some(radius) = number("radius") {
+                    ao.radius = radius as f32;
                 }
+                if let Some(strength) = number("strength") {
+                    ao.strength = strength as f32;
+                }
+                colorize.occlusion = Some(ao);
             }
             _ => {}
         }
+        Some(colorize)
     }
+
+    /// Ramp color at `t` in 0..1, linear rgba
+    fn sample(&self, t: f32) -> [f32; 4] {
+        let Some(last) = self.ramp.len().checked_sub(1) else {
+            return [1.0; 4];
+        };
+        let x = t.clamp(0.0, 1.0) * last as f32;
+        let i = (x.floor() as usize).min(last.saturating_sub(1));
+        let (a, b) = (self.ramp[i], self.ramp[(i + 1).min(last)]);
+        let a = Vec4::from(a.as_linear_rgba_f32());
+        let b = Vec4::from(b.as_linear_rgba_f32());
+        a.lerp(b, x - i as f32).to_array()
+    }
+
+    fn colors(&self, positions: &[[f32; 3]], normals: &[Vec3]) -> Vec<[f32; 4]> {
+        let direction = match self.mode {
+            ColorizeMode::Height => Vec3::Y,
+            _ => self.direction.normalize_or_zero(),
+        };
+        if self.mode == ColorizeMode::Normal {
+            return normals
+                .iter()
+                .map(|n| self.sample(0.5 + 0.5 * n.dot(direction)))
+                .collect();
+        }
+        let along: Vec<f32> = positions
+            .iter()
+            .map(|p| Vec3::from(*p).dot(direction))
+            .collect();
+        let min = along.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = along.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        along
+            .iter()
+            .map(|a| self.sample(a.map((min, max), (0.0, 1.0))))
+            .collect()
+    }
+}
+
+/// One normal per vertex of a non-indexed mesh, shared by the three corners of each face
+fn face_normals(positions: &[[f32; 3]]) -> Vec<Vec3> {
+    positions
+        .chunks(3)
+        .flat_map(|tri| {
+            let normal = match tri {
+                [a, b, c] => {
+                    let [a, b, c] = [a, b, c].map(|p| Vec3::from(*p));
+                    (b - a).cross(c - a).normalize_or_zero()
+                }
+                _ => Vec3::ZERO,
+            };
+            std::iter::repeat_n(normal, tri.len())
+        })
+        .collect()
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let paths = [
-        "room.gltf#Mesh2/Primitive0",
-        "room.gltf#Mesh3/Primitive0",
-        "room.gltf#Mesh4/Primitive0",
-    ];
-    let mut m = Meshes {
-        inner: HashSet::default(),
+/// The mesh's own normals, or ones averaged from its faces
+pub(crate) fn vertex_normals(mesh: &Mesh, positions: &[[f32; 3]]) -> Vec<Vec3> {
+    if let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+    {
+        return normals.iter().map(|n| Vec3::from(*n)).collect();
+    }
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U32(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        Some(Indices::U16(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        None => (0..positions.len()).collect(),
     };
-    for p in paths {
-        let h = asset_server.get_handle(p);
-        m.inner.insert(h);
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for tri in indices.chunks_exact(3) {
+        if tri.iter().any(|i| *i >= positions.len()) {
+            continue;
+        }
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vec3::from(positions[i]));
+        let face = (b - a).cross(c - a);
+        for i in tri {
+            normals[*i] += face;
+        }
+    }
+    normals.iter().map(|n| n.normalize_or_zero()).collect()
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct Colorized {
+    pending: HashMap<Handle<Mesh>, Colorize>, // waiting for their asset to load
+    // colors we wrote, fine to overwrite; the light preview multiplies its lighting onto these
+    pub(crate) base: HashMap<Handle<Mesh>, Arc<Vec<[f32; 4]>>>,
+}
+
+fn colorize_from_extras(
+    mut commands: Commands,
+    added: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+) {
+    for (entity, extras) in added.iter() {
+        if let Some(colorize) = Colorize::from_extras(&extras.value) {
+            commands.entity(entity).insert(colorize);
+        }
+    }
+}
+
+/// Closest `Colorize` on the entity or its ancestors
+fn settings_for<'a>(
+    entity: Entity,
+    settings: &'a Query<&Colorize>,
+    parents: &Query<&Parent>,
+) -> Option<&'a Colorize> {
+    std::iter::once(entity)
+        .chain(parents.iter_ancestors(entity))
+        .find_map(|e| settings.get(e).ok())
+}
+
+fn queue_meshes(
+    mut colorized: ResMut<Colorized>,
+    added: Query<Entity, Added<Handle<Mesh>>>,
+    changed: Query<Entity, Changed<Colorize>>,
+    children: Query<&Children>,
+    parents: Query<&Parent>,
+    settings: Query<&Colorize>,
+    meshes: Query<(&Handle<Mesh>, Option<&CutawayMesh>)>,
+) {
+    // new meshes, whenever they spawn, and everything under settings that changed
+    let changed = changed
+        .iter()
+        .flat_map(|root| std::iter::once(root).chain(children.iter_descendants(root)));
+    for entity in added.iter().chain(changed) {
+        let Ok((handle, cutaway)) = meshes.get(entity) else {
+            continue;
+        };
+        let Some(colorize) = settings_for(entity, &settings, &parents) else {
+            continue;
+        };
+        // cut away rooms are colored at the source and cut again from there
+        let handle = cutaway.map_or(handle, |c| &c.full);
+        colorized.pending.insert(handle.clone(), colorize.clone());
+    }
+}
+
+/// Occlusion being baked for a colorized mesh
+struct Bake {
+    handle: Handle<Mesh>,
+    settings: AmbientOcclusion,
+    base: Vec<[f32; 4]>,                  // colors before shading
+    task: Option<Task<Option<Vec<f32>>>>, // `None` until the room around it has settled
+}
+
+#[derive(Resource, Default)]
+struct Bakes(Vec<Bake>);
+
+fn check_mesh_loaded(
+    mut colorized: ResMut<Colorized>,
+    mut bakes: ResMut<Bakes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let loaded: Vec<_> = colorized
+        .pending
+        .keys()
+        .filter(|h| meshes.contains(*h))
+        .cloned()
+        .collect();
+    for handle in loaded {
+        let Some(colorize) = colorized.pending.remove(&handle) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get_mut(&handle) else {
+            continue;
+        };
+        if mesh.contains_attribute(Mesh::ATTRIBUTE_COLOR) && !colorized.base.contains_key(&handle) {
+            continue; // keep colors that came with the model, like a scan's
+        }
+        if colorize.mode == ColorizeMode::Normal && mesh.indices().is_some() {
+            // each face takes its color from its own normal, so faces can't share vertices
+            mesh.duplicate_vertices();
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let normals = match colorize.mode {
+            ColorizeMode::Normal => face_normals(positions),
+            _ => vec![],
+        };
+        let colors = colorize.colors(positions, &normals);
+        // an older bake for this mesh would shade the wrong colors
+        bakes.0.retain(|bake| bake.handle != handle);
+        if let Some(settings) = colorize.occlusion {
+            bakes.0.push(Bake {
+                handle: handle.clone_weak(),
+                settings,
+                base: colors.clone(),
+                task: None,
+            });
+        }
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors.clone());
+        colorized.base.insert(handle.clone_weak(), Arc::new(colors));
+    }
+}
+
+/// Meshes where they stand, cut away ones by their uncut source
+type PlacedMesh = (
+    &'static Handle<Mesh>,
+    &'static GlobalTransform,
+    Option<&'static CutawayMesh>,
+    Has<RoomMesh>,
+);
+
+/// Starts waiting bakes once every room mesh has loaded and stopped moving, so no occluder is missing
+fn start_bakes(
+    mut bakes: ResMut<Bakes>,
+    meshes: Res<Assets<Mesh>>,
+    bvhs: Res<MeshBvhs>,
+    geometry: RoomGeometry,
+    placed: Query<PlacedMesh>,
+    moved: Query<(), (With<RoomMesh>, Changed<GlobalTransform>)>,
+) {
+    if bakes.0.iter().all(|bake| bake.task.is_some()) || !moved.is_empty() || !geometry.settled() {
+        return;
+    }
+    let (room, fingerprint) = (Arc::new(geometry.snapshot()), geometry.fingerprint());
+    for bake in bakes.0.iter_mut().filter(|bake| bake.task.is_none()) {
+        let Some(mesh) = meshes.get(&bake.handle) else {
+            continue;
+        };
+        // shared meshes are shaded for the first place they're used
+        let Some((_, transform, _, in_room)) = placed
+            .iter()
+            .find(|(handle, _, cutaway, _)| cutaway.map_or(*handle, |c| &c.full) == &bake.handle)
+        else {
+            continue;
+        };
+        // the room hides parts of a mesh outside it, and so does the mesh itself
+        let own = if in_room {
+            None
+        } else {
+            let Some(bvh) = bvhs.get(&bake.handle) else {
+                continue;
+            };
+            Some(bvh.clone())
+        };
+        let (source, transform, settings, room) =
+            (mesh.clone(), *transform, bake.settings, room.clone());
+        bake.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            let key = ao::cache_key(&source, &transform, fingerprint, &settings);
+            if let Some(cached) = ao::load_cached(key) {
+                return Some(cached);
+            }
+            let occluders = |ray: Ray| {
+                let own = own
+                    .as_ref()
+                    .and_then(|bvh| bvh.intersect_world(ray, &transform));
+                room.cast(ray)
+                    .into_iter()
+                    .chain(own.map(|hit| hit.t))
+                    .min_by(f32::total_cmp)
+            };
+            let occlusion = ao::bake(&source, &transform, occluders, &settings)?;
+            ao::store_cached(key, &occlusion);
+            Some(occlusion)
+        }));
+    }
+}
+
+fn finish_bakes(
+    mut colorized: ResMut<Colorized>,
+    mut bakes: ResMut<Bakes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for mut bake in std::mem::take(&mut bakes.0) {
+        let finished = bake
+            .task
+            .as_mut()
+            .and_then(|task| future::block_on(future::poll_once(task)));
+        let Some(occlusion) = finished else {
+            bakes.0.push(bake); // waiting or still running
+            continue;
+        };
+        let Some(occlusion) = occlusion else {
+            continue;
+        };
+        let Some(mesh) = meshes.get_mut(&bake.handle) else {
+            continue;
+        };
+        if occlusion.len() != bake.base.len() {
+            continue;
+        }
+        let colors: Vec<[f32; 4]> = bake
+            .base
+            .iter()
+            .zip(&occlusion)
+            .map(|([r, g, b, a], o)| {
+                let shade = bake.settings.shade(*o);
+                [r * shade, g * shade, b * shade, *a]
+            })
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors.clone());
+        colorized.base.insert(bake.handle, Arc::new(colors));
     }
-    commands.insert_resource(m);
 }
 
 pub struct ColorizePlugin;
 impl Plugin for ColorizePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
-            .add_systems(Update, check_mesh_loaded);
+        app.register_type::<Colorize>()
+            .register_type::<ColorizeMode>()
+            .register_type::<AmbientOcclusion>()
+            .register_type::<Option<AmbientOcclusion>>()
+            .register_type::<Vec<Color>>()
+            .init_resource::<Colorized>()
+            .init_resource::<Bakes>()
+            .register_type::<LightPreview>()
+            .init_resource::<LightPreview>()
+            .init_resource::<Lighting>()
+            .add_systems(
+                Update,
+                (
+                    colorize_from_extras,
+                    queue_meshes,
+                    check_mesh_loaded,
+                    start_bakes,
+                    finish_bakes,
+                    preview_keys.run_if(not(egui_wants_keyboard)),
+                    light_preview,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matches_old_gradient() {
+        let colorize = Colorize::default();
+        let colors = colorize.colors(&[[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 0.0]], &[]);
+        assert!((colors[0][0] - 0.3).abs() < 1e-5);
+        assert!((colors[1][0] - 0.9).abs() < 1e-5);
+        assert!((colors[2][0] - 0.5).abs() < 1e-5);
+        assert_eq!(colors[0][3], 1.0);
+    }
+
+    #[test]
+    fn ramp_stops() {
+        let colorize = Colorize {
+            ramp: vec![Color::BLACK, Color::WHITE, Color::BLACK],
+            ..default()
+        };
+        assert_eq!(colorize.sample(0.0)[0], 0.0);
+        assert_eq!(colorize.sample(0.5)[0], 1.0);
+        assert!((colorize.sample(0.25)[0] - 0.5).abs() < 1e-5);
+        assert_eq!(colorize.sample(2.0)[0], 0.0);
+        // normal shading: facing the light is the top of the ramp
+        let normal = Colorize {
+            mode: ColorizeMode::Normal,
+            direction: Vec3::Y,
+            ..default()
+        };
+        let colors = normal.colors(&[[0.0; 3]; 2], &[Vec3::Y, Vec3::NEG_Y]);
+        assert!(colors[0][0] > colors[1][0]);
+    }
+
+    #[test]
+    fn settings_from_extras() {
+        assert_eq!(Colorize::from_extras(r#"{"name": "wall"}"#), None);
+        assert_eq!(Colorize::from_extras(r#"{"colorize": false}"#), None);
+        assert_eq!(
+            Colorize::from_extras(r#"{"colorize": true}"#),
+            Some(Colorize::default())
+        );
+        let colorize = Colorize::from_extras(
+            r##"{"colorize": {"mode": "height", "direction": [0, 0, 1], "ramp": ["#000000", "#ffffff"]}}"##,
+        )
+        .unwrap();
+        assert_eq!(colorize.mode, ColorizeMode::Height);
+        assert_eq!(colorize.direction, Vec3::Z);
+        assert_eq!(colorize.ramp, vec![Color::BLACK, Color::WHITE]);
     }
 }
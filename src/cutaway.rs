@@ -0,0 +1,543 @@
+// Dollhouse view: walls between the camera and the room are cut away (or drawn see-through) so
+// lamps on the near side stay visible, and a section plane slices off everything above a height.
+// Room meshes are swapped for cut copies; `CutawayMesh::full` keeps the original around.
+use crate::bvh::geometry_hash;
+use crate::fade::Faded;
+use crate::hover::{egui_wants_keyboard, MouseRaySource};
+use crate::room::{RoomBounds, RoomMesh};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use std::collections::HashSet;
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Cutaway {
+    pub enabled: bool,       // C toggles
+    pub opacity: f32,        // of the cut walls, 0 hides them
+    pub section: bool,       // backslash toggles
+    pub section_height: f32, // [ and ] move it
+}
+
+impl Default for Cutaway {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            opacity: 0.0,
+            section: false,
+            section_height: 2.0,
+        }
+    }
+}
+
+/// A room mesh currently drawn with parts cut away
+#[derive(Component)]
+pub struct CutawayMesh {
+    pub full: Handle<Mesh>,
+    geometry: u64,              // of the full mesh when it was cut
+    transform: Mat4,            // the triangles were taken to world space with
+    triangles: Vec<Triangle>,   // of the full mesh, so orbiting doesn't extract them again
+    view: Option<(Vec3, Vec3)>, // room center and camera the walls were cut for
+    cut: Vec<bool>,             // which triangles were cut, to tell when the view changed
+    section: Option<f32>,
+    sources: Vec<Source>,
+    shell: Option<(Entity, Handle<Mesh>, Vec<Source>)>,
+}
+
+/// Full mesh vertices a cut vertex lies between, and how far along.
+/// Lets color changes to the full mesh be copied over without cutting it again.
+type Source = (u32, u32, f32);
+
+/// See-through copy of the cut walls, child of the room mesh it was cut from
+#[derive(Component)]
+pub struct CutawayShell;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Vertex {
+    world: Vec3, // only used to decide what gets cut
+    position: Vec3,
+    normal: Vec3,
+    uv: Vec2,
+    source: Source,
+}
+
+impl Vertex {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            world: self.world.lerp(other.world, t),
+            position: self.position.lerp(other.position, t),
+            normal: self.normal.lerp(other.normal, t).normalize_or_zero(),
+            uv: self.uv.lerp(other.uv, t),
+            // the section only ever splits edges between two original vertices
+            source: (self.source.0, other.source.0, t),
+        }
+    }
+}
+
+type Triangle = [Vertex; 3];
+
+fn triangles(mesh: &Mesh, mat: &Mat4) -> Option<Vec<Triangle>> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return None;
+    }
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+        _ => None,
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+        _ => None,
+    };
+    let vertex = |i: usize| Vertex {
+        world: mat.transform_point3(Vec3::from(positions[i])),
+        position: Vec3::from(positions[i]),
+        normal: normals.map_or(Vec3::ZERO, |n| Vec3::from(n[i])),
+        uv: uvs.map_or(Vec2::ZERO, |uv| Vec2::from(uv[i])),
+        source: (i as u32, i as u32, 0.0),
+    };
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U32(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        Some(Indices::U16(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    Some(
+        indices
+            .chunks_exact(3)
+            .filter(|tri| tri.iter().all(|i| *i < positions.len()))
+            .map(|tri| [vertex(tri[0]), vertex(tri[1]), vertex(tri[2])])
+            .collect(),
+    )
+}
+
+/// Same attributes as `template`, one vertex per triangle corner
+fn build_mesh(triangles: &[Triangle], template: &Mesh) -> Mesh {
+    let corners = || triangles.iter().flatten();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        corners().map(|v| v.position.to_array()).collect::<Vec<_>>(),
+    );
+    if template.contains_attribute(Mesh::ATTRIBUTE_NORMAL) {
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            corners().map(|v| v.normal.to_array()).collect::<Vec<_>>(),
+        );
+    }
+    if template.contains_attribute(Mesh::ATTRIBUTE_UV_0) {
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            corners().map(|v| v.uv.to_array()).collect::<Vec<_>>(),
+        );
+    }
+    if let Some(colors) = vertex_colors(template) {
+        copy_colors(&mut mesh, &vertex_sources(triangles), colors);
+    }
+    mesh
+}
+
+fn vertex_sources(triangles: &[Triangle]) -> Vec<Source> {
+    triangles.iter().flatten().map(|v| v.source).collect()
+}
+
+fn vertex_colors(mesh: &Mesh) -> Option<&Vec<[f32; 4]>> {
+    match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+        Some(VertexAttributeValues::Float32x4(colors)) => Some(colors),
+        _ => None,
+    }
+}
+
+/// Bring a cut mesh's colors up to date with the `colors` of the mesh it was cut from
+fn copy_colors(mesh: &mut Mesh, sources: &[Source], colors: &[[f32; 4]]) {
+    let color = |i: u32| Vec4::from(colors.get(i as usize).copied().unwrap_or([1.0; 4]));
+    let copied: Vec<[f32; 4]> = sources
+        .iter()
+        .map(|(a, b, t)| color(*a).lerp(color(*b), *t).to_array())
+        .collect();
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, copied);
+}
+
+/// Part of `tri` below `height`, as zero to two triangles wound the same way
+fn clip_below(tri: Triangle, height: f32) -> Vec<Triangle> {
+    let mut polygon = Vec::with_capacity(4);
+    for i in 0..3 {
+        let (a, b) = (tri[i], tri[(i + 1) % 3]);
+        let (da, db) = (a.world.y - height, b.world.y - height);
+        if da <= 0.0 {
+            polygon.push(a);
+        }
+        if (da <= 0.0) != (db <= 0.0) {
+            polygon.push(a.lerp(b, da / (da - db)));
+        }
+    }
+    (1..polygon.len().saturating_sub(1))
+        .map(|i| [polygon[0], polygon[i], polygon[i + 1]])
+        .collect()
+}
+
+/// Wall facing the camera on the camera's side of the room
+fn blocks_view(tri: &Triangle, center: Vec3, camera: Vec3) -> bool {
+    let [a, b, c] = tri.map(|v| v.world);
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    let middle = (a + b + c) / 3.0;
+    let toward_camera = (camera - center) * Vec3::new(1.0, 0.0, 1.0);
+    // floors and ceilings never get in the way from above
+    normal.y.abs() < 0.5
+        && normal.dot(camera - middle) > 0.0
+        && (middle - center).dot(toward_camera) > 0.0
+}
+
+fn cutaway_keys(keys: Res<Input<KeyCode>>, mut settings: ResMut<Cutaway>) {
+    if keys.just_pressed(KeyCode::C) {
+        settings.enabled = !settings.enabled;
+    }
+    if keys.just_pressed(KeyCode::Backslash) {
+        settings.section = !settings.section;
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        settings.section_height -= 0.25;
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        settings.section_height += 0.25;
+    }
+}
+
+type CutTarget<'a> = (
+    Entity,
+    &'a RoomMesh,
+    &'a mut Handle<Mesh>,
+    &'a GlobalTransform,
+    &'a Handle<StandardMaterial>,
+    Option<&'a mut CutawayMesh>,
+);
+
+fn cut_walls(
+    mut commands: Commands,
+    settings: Res<Cutaway>,
+    bounds: Res<RoomBounds>,
+    mut events: EventReader<AssetEvent<Mesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    cameras: Query<&GlobalTransform, With<MouseRaySource>>,
+    mut room_meshes: Query<CutTarget>,
+) {
+    let modified: HashSet<Handle<Mesh>> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone_weak()),
+            _ => None,
+        })
+        .collect();
+    let camera = cameras.get_single().ok().map(|t| t.translation());
+    let section = settings.section.then_some(settings.section_height);
+    for (entity, room_mesh, mut handle, transform, material, mut cutaway) in room_meshes.iter_mut()
+    {
+        if !settings.enabled && section.is_none() {
+            if let Some(cutaway) = cutaway {
+                *handle = cutaway.full.clone();
+                if let Some((shell, ..)) = cutaway.shell {
+                    commands.entity(shell).despawn_recursive();
+                }
+                commands.entity(entity).remove::<CutawayMesh>();
+            }
+            continue;
+        }
+        let full = cutaway
+            .as_ref()
+            .map_or_else(|| handle.clone(), |c| c.full.clone());
+        let Some(full_mesh) = meshes.get(&full) else {
+            continue;
+        };
+        let modified = modified.contains(&full);
+        let geometry = match &cutaway {
+            Some(cutaway) if !modified => cutaway.geometry,
+            _ => geometry_hash(full_mesh),
+        };
+        // world space triangles are only taken again when the mesh changes shape or moves
+        let matrix = transform.compute_matrix();
+        let fresh = match &cutaway {
+            Some(c) if c.geometry == geometry && c.transform == matrix => None,
+            _ => match triangles(full_mesh, &matrix) {
+                Some(tris) => Some(tris),
+                None => continue,
+            },
+        };
+        let center = bounds
+            .rooms
+            .iter()
+            .find(|(room, _, _)| *room == room_mesh.room)
+            .map(|(_, min, max)| (*min + *max) / 2.0);
+        let view = match (settings.enabled, center, camera) {
+            (true, Some(center), Some(camera)) => Some((center, camera)),
+            _ => None,
+        };
+        let Some(tris) = fresh
+            .as_deref()
+            .or(cutaway.as_deref().map(|c| c.triangles.as_slice()))
+        else {
+            continue;
+        };
+        // nothing to classify again while neither the camera nor the room moved
+        let cut = match &cutaway {
+            Some(c) if fresh.is_none() && c.view == view => None,
+            _ => Some(
+                tris.iter()
+                    .map(|tri| {
+                        view.is_some_and(|(center, camera)| blocks_view(tri, center, camera))
+                    })
+                    .collect::<Vec<bool>>(),
+            ),
+        };
+        let unchanged = cutaway.as_ref().is_some_and(|c| {
+            cut.as_ref().is_none_or(|cut| *cut == c.cut)
+                && c.section == section
+                && c.geometry == geometry
+        });
+        if unchanged && !settings.is_changed() {
+            let Some(mut cutaway) = cutaway else {
+                continue;
+            };
+            if modified {
+                // only colors changed, e.g. colorize or the light preview
+                if let Some(colors) = vertex_colors(full_mesh).cloned() {
+                    if let Some(mesh) = meshes.get_mut(&handle) {
+                        copy_colors(mesh, &cutaway.sources, &colors);
+                    }
+                    if let Some((_, shell, sources)) = &cutaway.shell {
+                        if let Some(mesh) = meshes.get_mut(shell) {
+                            copy_colors(mesh, sources, &colors);
+                        }
+                    }
+                }
+            }
+            if let Some(fresh) = fresh {
+                cutaway.transform = matrix;
+                cutaway.triangles = fresh;
+            }
+            if cutaway.view != view {
+                cutaway.view = view;
+            }
+            continue;
+        }
+
+        let cut = cut.unwrap_or_else(|| cutaway.as_ref().map_or(vec![], |c| c.cut.clone()));
+        let clip = |tri: &Triangle| match section {
+            Some(height) => clip_below(*tri, height),
+            None => vec![*tri],
+        };
+        let pieces = |wanted: bool| -> Vec<Triangle> {
+            tris.iter()
+                .zip(&cut)
+                .filter(|(_, c)| **c == wanted)
+                .flat_map(|(tri, _)| clip(tri))
+                .collect()
+        };
+        let kept = pieces(false);
+        let sources = vertex_sources(&kept);
+        let kept = build_mesh(&kept, full_mesh);
+        let shell = pieces(true);
+        let shell = (settings.opacity > 0.0 && !shell.is_empty())
+            .then(|| (build_mesh(&shell, full_mesh), vertex_sources(&shell)));
+        let triangles = fresh.unwrap_or_else(|| {
+            cutaway
+                .as_mut()
+                .map_or(vec![], |c| std::mem::take(&mut c.triangles))
+        });
+
+        // the shell keeps its entity and mesh, only what's in it changes
+        let old_shell = cutaway.as_mut().and_then(|c| c.shell.take());
+        let shell = match (shell, old_shell) {
+            (Some((mesh, sources)), Some((shell, handle, _))) => {
+                if let Some(old) = meshes.get_mut(&handle) {
+                    *old = mesh;
+                }
+                if settings.is_changed() {
+                    commands.entity(shell).insert(Faded(settings.opacity));
+                }
+                Some((shell, handle, sources))
+            }
+            (Some((mesh, sources)), None) => {
+                let mesh = meshes.add(mesh);
+                let shell = commands
+                    .spawn((
+                        Name::new("cutaway"),
+                        CutawayShell,
+                        Faded(settings.opacity),
+                        PbrBundle {
+                            mesh: mesh.clone(),
+                            material: material.clone(),
+                            ..default()
+                        },
+                    ))
+                    .id();
+                commands.entity(entity).add_child(shell);
+                Some((shell, mesh, sources))
+            }
+            (None, Some((shell, ..))) => {
+                commands.entity(shell).despawn_recursive();
+                None
+            }
+            (None, None) => None,
+        };
+        match cutaway {
+            Some(mut cutaway) => {
+                if let Some(mesh) = meshes.get_mut(&handle) {
+                    *mesh = kept;
+                }
+                *cutaway = CutawayMesh {
+                    full,
+                    geometry,
+                    transform: matrix,
+                    triangles,
+                    view,
+                    cut,
+                    section,
+                    sources,
+                    shell,
+                };
+            }
+            None => {
+                *handle = meshes.add(kept);
+                commands.entity(entity).insert(CutawayMesh {
+                    full,
+                    geometry,
+                    transform: matrix,
+                    triangles,
+                    view,
+                    cut,
+                    section,
+                    sources,
+                    shell,
+                });
+            }
+        }
+    }
+}
+
+pub struct CutawayPlugin;
+
+impl Plugin for CutawayPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Cutaway>()
+            .init_resource::<Cutaway>()
+            .add_systems(
+                Update,
+                (
+                    cutaway_keys.run_if(not(egui_wants_keyboard)),
+                    cut_walls.after(cutaway_keys),
+                ),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tri(points: [Vec3; 3]) -> Triangle {
+        points.map(|p| Vertex {
+            world: p,
+            position: p,
+            normal: Vec3::Z,
+            uv: Vec2::new(p.x, p.y),
+            source: (0, 0, 0.0),
+        })
+    }
+
+    fn area(tri: &Triangle) -> f32 {
+        let [a, b, c] = tri.map(|v| v.world);
+        (b - a).cross(c - a).length() / 2.0
+    }
+
+    #[test]
+    fn section_clips_triangles() {
+        let t = tri([
+            Vec3::ZERO,
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+        ]);
+        // fully below or above
+        assert_eq!(clip_below(t, 3.0), vec![t]);
+        assert!(clip_below(t, -1.0).is_empty());
+        // halfway: the trapezoid under y = 1 is 3/4 of the triangle
+        let pieces = clip_below(t, 1.0);
+        assert_eq!(pieces.len(), 2);
+        let total: f32 = pieces.iter().map(area).sum();
+        assert!((total - 1.5).abs() < 1e-5);
+        for piece in &pieces {
+            assert!(piece.iter().all(|v| v.world.y <= 1.0 + 1e-5));
+            // winding is kept
+            let [a, b, c] = piece.map(|v| v.world);
+            assert!((b - a).cross(c - a).z > 0.0);
+            // attributes follow the position
+            assert!(piece
+                .iter()
+                .all(|v| v.uv.distance(v.world.truncate()) < 1e-5));
+        }
+    }
+
+    #[test]
+    fn colors_follow_the_cut() {
+        let mut t = tri([
+            Vec3::ZERO,
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+        ]);
+        for (i, v) in t.iter_mut().enumerate() {
+            v.source = (i as u32, i as u32, 0.0);
+        }
+        let pieces = clip_below(t, 1.0);
+        // red, green and blue corners: a vertex's color is its barycentric coordinates
+        let expected = |p: Vec3| Vec4::new(1.0 - p.x / 2.0 - p.y / 2.0, p.x / 2.0, p.y / 2.0, 1.0);
+        let mut full = Mesh::new(PrimitiveTopology::TriangleList);
+        full.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            vec![
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0, 1.0],
+            ],
+        );
+        let mut mesh = build_mesh(&pieces, &full);
+        let colors = |mesh: &Mesh| {
+            vertex_colors(mesh)
+                .expect("cut mesh lost its colors")
+                .clone()
+        };
+        for (v, color) in pieces.iter().flatten().zip(colors(&mesh)) {
+            assert!(expected(v.world).distance(Vec4::from(color)) < 1e-5);
+        }
+        // recoloring the full mesh carries over without cutting again
+        let gray = vec![[0.5, 0.5, 0.5, 1.0]; 3];
+        copy_colors(&mut mesh, &vertex_sources(&pieces), &gray);
+        assert!(colors(&mesh).iter().all(|c| *c == [0.5, 0.5, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn only_near_walls_block() {
+        let center = Vec3::new(0.0, 1.0, 0.0);
+        let camera = Vec3::new(0.0, 10.0, 20.0);
+        // wall at z = 5 facing +z, toward the camera
+        let near = tri([
+            Vec3::new(-1.0, 0.0, 5.0),
+            Vec3::new(1.0, 0.0, 5.0),
+            Vec3::new(0.0, 2.0, 5.0),
+        ]);
+        assert!(blocks_view(&near, center, camera));
+        // wall at z = -5 facing +z: faces the camera but is the back of the room
+        let far = near.map(|v| Vertex {
+            world: v.world - Vec3::new(0.0, 0.0, 10.0),
+            ..v
+        });
+        assert!(!blocks_view(&far, center, camera));
+        // floor near the camera
+        let floor = tri([
+            Vec3::new(-1.0, 0.0, 4.0),
+            Vec3::new(1.0, 0.0, 4.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        ]);
+        assert!(!blocks_view(&floor, center, camera));
+    }
+}
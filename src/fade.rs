@@ -0,0 +1,75 @@
+// See-through versions of room materials, for floors above the one being looked at
+// and walls in the way of the camera. Removing `Faded` puts the original material back.
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// Draw this mesh at `0` (invisible) to `1` (opaque) of its usual opacity
+#[derive(Component, Clone, Copy, PartialEq)]
+pub struct Faded(pub f32);
+
+/// Material the mesh had before it was faded
+#[derive(Component)]
+struct FadeOriginal(Handle<StandardMaterial>);
+
+/// Faded copies, shared between meshes that had the same material and opacity
+#[derive(Resource, Default)]
+struct FadedMaterials(HashMap<(Handle<StandardMaterial>, u8), Handle<StandardMaterial>>);
+
+type FadeTarget<'a> = (
+    Entity,
+    &'a Faded,
+    &'a Handle<StandardMaterial>,
+    Option<&'a FadeOriginal>,
+);
+
+fn apply_fades(
+    mut commands: Commands,
+    mut faded_materials: ResMut<FadedMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    changed: Query<FadeTarget, Changed<Faded>>,
+) {
+    for (entity, faded, current, original) in changed.iter() {
+        let original = original.map_or_else(|| current.clone(), |o| o.0.clone());
+        let alpha = (faded.0.clamp(0.0, 1.0) * 255.0) as u8;
+        let handle = faded_materials
+            .0
+            .entry((original.clone(), alpha))
+            .or_insert_with(|| {
+                let mut material = materials.get(&original).cloned().unwrap_or_default();
+                material
+                    .base_color
+                    .set_a(material.base_color.a() * alpha as f32 / 255.0);
+                material.alpha_mode = AlphaMode::Blend;
+                materials.add(material)
+            })
+            .clone();
+        commands
+            .entity(entity)
+            .insert((handle, FadeOriginal(original)));
+    }
+}
+
+fn restore_faded(
+    mut commands: Commands,
+    mut removed: RemovedComponents<Faded>,
+    originals: Query<&FadeOriginal>,
+) {
+    for entity in removed.iter() {
+        let Ok(original) = originals.get(entity) else {
+            continue;
+        };
+        commands
+            .entity(entity)
+            .insert(original.0.clone())
+            .remove::<FadeOriginal>();
+    }
+}
+
+pub struct FadePlugin;
+
+impl Plugin for FadePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FadedMaterials>()
+            .add_systems(PostUpdate, (apply_fades, restore_faded));
+    }
+}
@@ -0,0 +1,224 @@
+// Maps ghost distance to how strongly a bulb reacts, always in [0, 1]
+use crate::util::MapRange;
+use bevy::prelude::*;
+
+#[derive(Reflect, Clone, Copy, Default, PartialEq)]
+pub struct CurvePoint {
+    pub distance: f32, // 0 at `DistanceBounds::min`, 1 at `DistanceBounds::max`
+    pub strength: f32,
+}
+
+#[derive(Reflect, Clone, Default, PartialEq)]
+pub enum Falloff {
+    #[default]
+    LinearClamped,
+    /// 1/d^2 from the near bound, shifted so it reaches 0 at the far bound
+    InverseSquare,
+    Smoothstep,
+    Gaussian {
+        sigma: f32, // in normalized distance, 0.3 is a good start
+    },
+    Stepped {
+        steps: u32,
+    },
+    /// Piecewise linear through the points, kept sorted by distance
+    Custom(Vec<CurvePoint>),
+}
+
+impl Falloff {
+    /// Puts custom points back in order after an edit, `false` if they already were
+    fn sort(&mut self) -> bool {
+        let Falloff::Custom(points) = self else {
+            return false;
+        };
+        let sorted = points
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance);
+        if !sorted {
+            points.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        }
+        !sorted
+    }
+
+    pub fn eval(&self, distance: f32, bounds: (f32, f32)) -> f32 {
+        let (near, far) = bounds;
+        let x = if far > near {
+            ((distance - near) / (far - near)).clamp(0.0, 1.0)
+        } else if distance <= near {
+            0.0
+        } else {
+            1.0
+        };
+
+        let strength = match self {
+            Falloff::LinearClamped => 1.0 - x,
+            Falloff::InverseSquare => {
+                let near = near.max(f32::EPSILON);
+                let d = distance.clamp(near, far.max(near));
+                let floor = (near / far.max(near)).powi(2);
+                let raw = (near / d).powi(2);
+                if floor < 1.0 {
+                    (raw - floor) / (1.0 - floor)
+                } else {
+                    1.0 - x
+                }
+            }
+            Falloff::Smoothstep => 1.0 - x * x * (3.0 - 2.0 * x),
+            Falloff::Gaussian { sigma } => {
+                let sigma = sigma.max(f32::EPSILON);
+                (-(x * x) / (2.0 * sigma * sigma)).exp()
+            }
+            Falloff::Stepped { steps } => {
+                let steps = (*steps).max(1) as f32;
+                ((1.0 - x) * steps).ceil() / steps
+            }
+            Falloff::Custom(points) => piecewise_linear(points, x),
+        };
+        strength.clamp(0.0, 1.0)
+    }
+}
+
+/// `points` sorted by distance
+fn piecewise_linear(points: &[CurvePoint], x: f32) -> f32 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return 1.0 - x; // nothing drawn yet, behave like linear
+    };
+    if x <= first.distance {
+        return first.strength;
+    }
+    if x >= last.distance {
+        return last.strength;
+    }
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if x <= b.distance {
+            return x.map((a.distance, b.distance), (a.strength, b.strength));
+        }
+    }
+    last.strength
+}
+
+/// Separate curves for the on-screen `PointLight`s and the real bulbs,
+/// the real ones usually look better with a gentler curve
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct FalloffSettings {
+    pub virtual_curve: Falloff,
+    pub real_curve: Falloff,
+}
+
+/// Points dragged past each other in the inspector are sorted once, not on every eval
+pub(crate) fn sort_curves(mut settings: ResMut<FalloffSettings>) {
+    if !settings.is_changed() {
+        return;
+    }
+    // sorting in place doesn't count as another change
+    let settings = settings.bypass_change_detection();
+    settings.virtual_curve.sort();
+    settings.real_curve.sort();
+}
+
+pub struct FalloffPlugin;
+
+impl Plugin for FalloffPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<FalloffSettings>()
+            .register_type::<Falloff>()
+            .register_type::<CurvePoint>()
+            .register_type::<Vec<CurvePoint>>()
+            .init_resource::<FalloffSettings>()
+            .add_systems(Update, sort_curves);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: (f32, f32) = (1.0, 30.0);
+
+    fn all_curves() -> Vec<Falloff> {
+        vec![
+            Falloff::LinearClamped,
+            Falloff::InverseSquare,
+            Falloff::Smoothstep,
+            Falloff::Gaussian { sigma: 0.3 },
+            Falloff::Stepped { steps: 4 },
+            Falloff::Custom(vec![
+                CurvePoint {
+                    distance: 0.0,
+                    strength: 1.0,
+                },
+                CurvePoint {
+                    distance: 0.5,
+                    strength: 0.8,
+                },
+                CurvePoint {
+                    distance: 1.0,
+                    strength: 0.0,
+                },
+            ]),
+        ]
+    }
+
+    #[test]
+    fn stays_in_unit_range() {
+        for curve in all_curves() {
+            for d in [-10.0, 0.0, 0.5, 1.0, 7.0, 15.5, 30.0, 100.0] {
+                let s = curve.eval(d, BOUNDS);
+                assert!((0.0..=1.0).contains(&s), "{d} -> {s}");
+            }
+        }
+    }
+
+    #[test]
+    fn full_at_near_bound() {
+        for curve in all_curves() {
+            assert_eq!(curve.eval(BOUNDS.0, BOUNDS), 1.0);
+        }
+    }
+
+    #[test]
+    fn never_brighter_further_away() {
+        for curve in all_curves() {
+            let mut last = 1.0;
+            for i in 0..=40 {
+                let s = curve.eval(i as f32, BOUNDS);
+                assert!(s <= last + 1e-6);
+                last = s;
+            }
+        }
+    }
+
+    #[test]
+    fn linear_clamps_instead_of_extrapolating() {
+        assert_eq!(Falloff::LinearClamped.eval(0.0, BOUNDS), 1.0);
+        assert_eq!(Falloff::LinearClamped.eval(60.0, BOUNDS), 0.0);
+        assert_eq!(Falloff::LinearClamped.eval(15.5, BOUNDS), 0.5);
+    }
+
+    #[test]
+    fn custom_interpolates_between_points() {
+        let curve = &all_curves()[5];
+        assert!((curve.eval(8.25, BOUNDS) - 0.9).abs() < 1e-5);
+    }
+
+    #[test]
+    fn custom_points_sorted_after_edits() {
+        let mut curve = all_curves()[5].clone();
+        assert!(!curve.sort());
+        let Falloff::Custom(points) = &mut curve else {
+            unreachable!()
+        };
+        points.swap(0, 2);
+        assert!(curve.sort());
+        assert!(curve == all_curves()[5]);
+        assert!(!Falloff::Smoothstep.sort());
+    }
+
+    #[test]
+    fn degenerate_bounds() {
+        assert_eq!(Falloff::Smoothstep.eval(5.0, (5.0, 5.0)), 1.0);
+        assert_eq!(Falloff::Smoothstep.eval(6.0, (5.0, 5.0)), 0.0);
+    }
+}
@@ -0,0 +1,637 @@
+// Builds a room from a 2D floor plan instead of a modelled scene:
+// the outline becomes the floor and ceiling, and walls are extruded up with holes for doors and windows.
+// Plans are JSON, or SVG where the first closed path/polygon is the outline,
+// other lines/paths are inner walls, and lines with class "door" or "window" cut openings.
+// SVG units are centimeters unless the root has data-scale (meters per unit).
+use anyhow::{anyhow, Error};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct FloorPlan {
+    #[serde(default = "default_wall_height")]
+    pub wall_height: f32,
+    #[serde(default = "default_wall_thickness")]
+    pub wall_thickness: f32,
+    pub outline: Vec<[f32; 2]>, // x, z of the floor polygon
+    #[serde(default)]
+    pub walls: Vec<Wall>, // inner walls, the outline edges are walls too
+    #[serde(default)]
+    pub openings: Vec<Opening>,
+}
+
+fn default_wall_height() -> f32 {
+    2.5
+}
+
+fn default_wall_thickness() -> f32 {
+    0.1
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Wall {
+    pub from: [f32; 2],
+    pub to: [f32; 2],
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Opening {
+    Door {
+        wall: usize, // index into the outline edges followed by `walls`
+        offset: f32, // from the start of the wall to the near side of the opening
+        width: f32,
+        #[serde(default = "default_door_height")]
+        height: f32,
+    },
+    Window {
+        wall: usize,
+        offset: f32,
+        width: f32,
+        #[serde(default = "default_sill")]
+        sill: f32,
+        #[serde(default = "default_window_top")]
+        top: f32,
+    },
+}
+
+fn default_door_height() -> f32 {
+    2.1
+}
+
+fn default_sill() -> f32 {
+    0.9
+}
+
+fn default_window_top() -> f32 {
+    2.1
+}
+
+impl Opening {
+    fn wall(&self) -> usize {
+        match self {
+            Opening::Door { wall, .. } | Opening::Window { wall, .. } => *wall,
+        }
+    }
+
+    /// Along the wall from, to; then height from, to
+    fn span(&self) -> (f32, f32, f32, f32) {
+        match *self {
+            Opening::Door {
+                offset,
+                width,
+                height,
+                ..
+            } => (offset, offset + width, 0.0, height),
+            Opening::Window {
+                offset,
+                width,
+                sill,
+                top,
+                ..
+            } => (offset, offset + width, sill, top),
+        }
+    }
+}
+
+impl FloorPlan {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::read_to_string(path)?;
+        if path.ends_with(".svg") {
+            parse_svg(&file)
+        } else {
+            Ok(serde_json::from_str(&file)?)
+        }
+    }
+
+    /// Outline edges, then the inner walls
+    fn all_walls(&self) -> Vec<Wall> {
+        let n = self.outline.len();
+        (0..n)
+            .map(|i| Wall {
+                from: self.outline[i],
+                to: self.outline[(i + 1) % n],
+            })
+            .chain(self.walls.iter().copied())
+            .collect()
+    }
+
+    /// Floor, walls and ceiling
+    pub fn meshes(&self) -> Result<Vec<(&'static str, Mesh)>, Error> {
+        if self.outline.len() < 3 {
+            return Err(anyhow!("floor plan outline needs at least 3 points"));
+        }
+        if self.wall_thickness.is_nan() || self.wall_thickness <= 0.0 {
+            return Err(anyhow!(
+                "wall thickness must be positive, not {}",
+                self.wall_thickness
+            ));
+        }
+        let all_walls = self.all_walls();
+        if let Some((i, opening)) = self
+            .openings
+            .iter()
+            .enumerate()
+            .find(|(_, o)| o.wall() >= all_walls.len())
+        {
+            return Err(anyhow!(
+                "opening {i} ({opening:?}) is on wall {}, but there are only {} walls",
+                opening.wall(),
+                all_walls.len()
+            ));
+        }
+        let outline: Vec<Vec2> = self.outline.iter().map(|p| Vec2::from(*p)).collect();
+        let triangles = triangulate(&outline);
+
+        let mut floor = MeshBuilder::default();
+        let mut ceiling = MeshBuilder::default();
+        for [a, b, c] in &triangles {
+            let at = |i: usize, y: f32| Vec3::new(outline[i].x, y, outline[i].y);
+            floor.triangle([at(*a, 0.0), at(*b, 0.0), at(*c, 0.0)], Vec3::Y);
+            ceiling.triangle(
+                [
+                    at(*a, self.wall_height),
+                    at(*b, self.wall_height),
+                    at(*c, self.wall_height),
+                ],
+                Vec3::NEG_Y,
+            );
+        }
+
+        let mut walls = MeshBuilder::default();
+        for (i, wall) in all_walls.iter().enumerate() {
+            let openings: Vec<_> = self
+                .openings
+                .iter()
+                .filter(|o| o.wall() == i)
+                .map(Opening::span)
+                .collect();
+            let (from, to) = (Vec2::from(wall.from), Vec2::from(wall.to));
+            for (u0, u1, v0, v1) in wall_pieces(from.distance(to), self.wall_height, &openings) {
+                walls.wall_box(from, to, self.wall_thickness, (u0, u1), (v0, v1));
+            }
+        }
+        Ok(vec![
+            ("floor", floor.build()),
+            ("walls", walls.build()),
+            ("ceiling", ceiling.build()),
+        ])
+    }
+}
+
+/// Solid rectangles (along from, to, height from, to) left of a wall once its openings are cut out
+fn wall_pieces(
+    length: f32,
+    height: f32,
+    openings: &[(f32, f32, f32, f32)],
+) -> Vec<(f32, f32, f32, f32)> {
+    let mut openings: Vec<_> = openings
+        .iter()
+        .map(|(u0, u1, v0, v1)| {
+            (
+                u0.clamp(0.0, length),
+                u1.clamp(0.0, length),
+                v0.clamp(0.0, height),
+                v1.clamp(0.0, height),
+            )
+        })
+        .filter(|(u0, u1, v0, v1)| u1 > u0 && v1 > v0)
+        .collect();
+    openings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut pieces = vec![];
+    let mut u = 0.0;
+    for (u0, u1, v0, v1) in openings {
+        if u0 > u {
+            pieces.push((u, u0, 0.0, height));
+        }
+        let u0 = u0.max(u); // overlapping openings just share the cut
+        if u1 > u0 {
+            if v0 > 0.0 {
+                pieces.push((u0, u1, 0.0, v0));
+            }
+            if v1 < height {
+                pieces.push((u0, u1, v1, height));
+            }
+        }
+        u = u.max(u1);
+    }
+    if u < length {
+        pieces.push((u, length, 0.0, height));
+    }
+    pieces
+}
+
+/// Ear clipping, works for concave outlines in either winding
+fn triangulate(points: &[Vec2]) -> Vec<[usize; 3]> {
+    let area: f32 = (0..points.len())
+        .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+        .sum();
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    if area < 0.0 {
+        remaining.reverse(); // counter clockwise from here on
+    }
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            let (pa, pb, pc) = (points[a], points[b], points[c]);
+            if (pb - pa).perp_dot(pc - pb) <= 0.0 {
+                return false; // reflex corner
+            }
+            !remaining
+                .iter()
+                .filter(|j| ![a, b, c].contains(j))
+                .any(|j| in_triangle(points[*j], pa, pb, pc))
+        });
+        // a degenerate outline has no ears left, clip anyway so this always ends
+        let i = ear.unwrap_or(0);
+        triangles.push([
+            remaining[(i + n - 1) % n],
+            remaining[i],
+            remaining[(i + 1) % n],
+        ]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+fn in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let d1 = (b - a).perp_dot(p - a);
+    let d2 = (c - b).perp_dot(p - b);
+    let d3 = (a - c).perp_dot(p - c);
+    d1 >= 0.0 && d2 >= 0.0 && d3 >= 0.0
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// Wound counter clockwise as seen from the side `normal` points to, whatever order it's given in
+    fn triangle(&mut self, [a, b, c]: [Vec3; 3], normal: Vec3) {
+        let (b, c) = if (b - a).cross(c - a).dot(normal) < 0.0 {
+            (c, b)
+        } else {
+            (b, c)
+        };
+        let start = self.positions.len() as u32;
+        for v in [a, b, c] {
+            self.positions.push(v.to_array());
+            self.normals.push(normal.to_array());
+        }
+        self.indices.extend([start, start + 1, start + 2]);
+    }
+
+    fn quad(&mut self, [a, b, c, d]: [Vec3; 4], normal: Vec3) {
+        self.triangle([a, b, c], normal);
+        self.triangle([a, c, d], normal);
+    }
+
+    /// Box along the wall from `from` to `to` (x, z), covering `along` and `height` of it
+    fn wall_box(
+        &mut self,
+        from: Vec2,
+        to: Vec2,
+        thickness: f32,
+        along: (f32, f32),
+        height: (f32, f32),
+    ) {
+        let dir = (to - from).normalize_or_zero();
+        if dir == Vec2::ZERO {
+            return;
+        }
+        let side = dir.perp() * thickness / 2.0;
+        let corner = |u: f32, s: f32, y: f32| {
+            let p = from + dir * u + side * s;
+            Vec3::new(p.x, y, p.y)
+        };
+        let (u0, u1) = along;
+        let (y0, y1) = height;
+        let dir3 = Vec3::new(dir.x, 0.0, dir.y);
+        let side3 = Vec3::new(side.x, 0.0, side.y).normalize();
+        for s in [-1.0, 1.0] {
+            // the two faces of the wall
+            self.quad(
+                [
+                    corner(u0, s, y0),
+                    corner(u1, s, y0),
+                    corner(u1, s, y1),
+                    corner(u0, s, y1),
+                ],
+                side3 * s,
+            );
+        }
+        for (u, n) in [(u0, -dir3), (u1, dir3)] {
+            // ends, also the reveals of doors and windows
+            self.quad(
+                [
+                    corner(u, -1.0, y0),
+                    corner(u, 1.0, y0),
+                    corner(u, 1.0, y1),
+                    corner(u, -1.0, y1),
+                ],
+                n,
+            );
+        }
+        for (y, n) in [(y0, Vec3::NEG_Y), (y1, Vec3::Y)] {
+            // bottom and top, sills and lintels
+            self.quad(
+                [
+                    corner(u0, -1.0, y),
+                    corner(u1, -1.0, y),
+                    corner(u1, 1.0, y),
+                    corner(u0, 1.0, y),
+                ],
+                n,
+            );
+        }
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+/// Value of `name="..."` in a tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn numbers(text: &str) -> Vec<f32> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|n| n.parse().ok())
+        .collect()
+}
+
+/// Points of a path made of M, L, H, V and Z commands (either case), and whether it's closed
+fn parse_path(d: &str) -> Result<(Vec<Vec2>, bool), Error> {
+    // split commands from their numbers
+    let mut tokens = vec![];
+    let mut number = String::new();
+    for c in d.chars() {
+        if c.is_ascii_alphabetic() {
+            tokens.extend(numbers(&number).into_iter().map(Err));
+            number.clear();
+            tokens.push(Ok(c));
+        } else {
+            number.push(c);
+        }
+    }
+    tokens.extend(numbers(&number).into_iter().map(Err));
+
+    let mut points: Vec<Vec2> = vec![];
+    let mut closed = false;
+    let mut command = 'M';
+    let mut tokens = tokens.into_iter().peekable();
+    let next = |tokens: &mut std::iter::Peekable<_>| match tokens.next() {
+        Some(Err(n)) => Ok(n),
+        _ => Err(anyhow!("path {d:?} is missing a number")),
+    };
+    while let Some(token) = tokens.peek().copied() {
+        if let Ok(c) = token {
+            command = c;
+            tokens.next();
+            if c.eq_ignore_ascii_case(&'z') {
+                closed = true;
+            }
+            continue;
+        }
+        let last = points.last().copied().unwrap_or(Vec2::ZERO);
+        let relative = command.is_ascii_lowercase();
+        let base = if relative { last } else { Vec2::ZERO };
+        let point = match command.to_ascii_uppercase() {
+            'M' | 'L' => base + Vec2::new(next(&mut tokens)?, next(&mut tokens)?),
+            'H' => Vec2::new(base.x + next(&mut tokens)?, last.y),
+            'V' => Vec2::new(last.x, base.y + next(&mut tokens)?),
+            c => return Err(anyhow!("unsupported path command {c}")),
+        };
+        points.push(point);
+    }
+    Ok((points, closed))
+}
+
+fn parse_svg(svg: &str) -> Result<FloorPlan, Error> {
+    let mut outline: Option<Vec<Vec2>> = None;
+    let mut walls = vec![];
+    let mut openings = vec![];
+    let mut scale = 0.01;
+    for tag in svg.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        let (points, closed): (Vec<Vec2>, bool) = match name {
+            "svg" => {
+                if let Some(s) = attribute(tag, "data-scale") {
+                    scale = s.parse()?;
+                }
+                continue;
+            }
+            "path" => parse_path(attribute(tag, "d").unwrap_or_default())?,
+            "polygon" | "polyline" => {
+                let n = numbers(attribute(tag, "points").unwrap_or_default());
+                let points = n.chunks_exact(2).map(|p| Vec2::new(p[0], p[1])).collect();
+                (points, name == "polygon")
+            }
+            "line" => {
+                let get = |a| {
+                    attribute(tag, a)
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0.0)
+                };
+                (
+                    vec![
+                        Vec2::new(get("x1"), get("y1")),
+                        Vec2::new(get("x2"), get("y2")),
+                    ],
+                    false,
+                )
+            }
+            _ => continue,
+        };
+        let points: Vec<Vec2> = points.into_iter().map(|p| p * scale).collect();
+        // class can hold several names, like "door interior"
+        let kind = attribute(tag, "class").and_then(|class| {
+            class
+                .split_whitespace()
+                .find(|c| matches!(*c, "door" | "window"))
+        });
+        match kind {
+            Some(kind) if points.len() >= 2 => {
+                openings.push((kind == "door", points[0], points[points.len() - 1]))
+            }
+            _ if closed && outline.is_none() => outline = Some(points),
+            _ => walls.extend(points.windows(2).map(|w| Wall {
+                from: w[0].to_array(),
+                to: w[1].to_array(),
+            })),
+        }
+    }
+    let outline = outline.ok_or_else(|| anyhow!("no closed path or polygon for the outline"))?;
+    let mut plan = FloorPlan {
+        wall_height: default_wall_height(),
+        wall_thickness: default_wall_thickness(),
+        outline: outline.iter().map(|p| p.to_array()).collect(),
+        walls,
+        openings: vec![],
+    };
+    // openings go on the wall their middle is closest to
+    let all_walls = plan.all_walls();
+    for (door, a, b) in openings {
+        let mid = (a + b) / 2.0;
+        let Some((wall, start)) = all_walls
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (i, Vec2::from(w.from), Vec2::from(w.to)))
+            .min_by(|x, y| {
+                distance_to_segment(mid, x.1, x.2).total_cmp(&distance_to_segment(mid, y.1, y.2))
+            })
+            .map(|(i, from, to)| {
+                let dir = (to - from).normalize_or_zero();
+                (i, (a - from).dot(dir).min((b - from).dot(dir)))
+            })
+        else {
+            continue;
+        };
+        let width = a.distance(b);
+        plan.openings.push(if door {
+            Opening::Door {
+                wall,
+                offset: start,
+                width,
+                height: default_door_height(),
+            }
+        } else {
+            Opening::Window {
+                wall,
+                offset: start,
+                width,
+                sill: default_sill(),
+                top: default_window_top(),
+            }
+        });
+    }
+    Ok(plan)
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    p.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(points: &[Vec2], triangles: &[[usize; 3]]) -> f32 {
+        triangles
+            .iter()
+            .map(|[a, b, c]| (points[*b] - points[*a]).perp_dot(points[*c] - points[*a]) / 2.0)
+            .sum()
+    }
+
+    #[test]
+    fn triangulates_concave_outline() {
+        // L shaped, 3 of a 2x2 square, clockwise
+        let l = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.0, 2.0),
+            Vec2::new(1.0, 2.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(2.0, 0.0),
+        ];
+        let triangles = triangulate(&l);
+        assert_eq!(triangles.len(), 4);
+        assert!((area(&l, &triangles) - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn door_and_window_cut_the_wall() {
+        let pieces = wall_pieces(5.0, 2.5, &[(1.0, 2.0, 0.0, 2.1), (3.0, 4.0, 0.9, 2.1)]);
+        let area: f32 = pieces
+            .iter()
+            .map(|(u0, u1, v0, v1)| (u1 - u0) * (v1 - v0))
+            .sum();
+        assert!((area - (5.0 * 2.5 - 2.1 - 1.2)).abs() < 1e-5);
+        // nothing below the door
+        assert!(!pieces.iter().any(|p| p.0 == 1.0 && p.2 == 0.0));
+    }
+
+    #[test]
+    fn json_plan() {
+        let plan: FloorPlan = serde_json::from_str(
+            r#"{
+                "outline": [[0, 0], [4, 0], [4, 3], [0, 3]],
+                "openings": [{"kind": "door", "wall": 0, "offset": 1, "width": 0.9}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(plan.wall_height, 2.5);
+        assert_eq!(plan.openings[0].span(), (1.0, 1.9, 0.0, 2.1));
+        let meshes = plan.meshes().unwrap();
+        assert_eq!(meshes.len(), 3);
+    }
+
+    #[test]
+    fn bad_plans_are_errors() {
+        let plan: FloorPlan = serde_json::from_str(
+            r#"{
+                "outline": [[0, 0], [4, 0], [4, 3], [0, 3]],
+                "openings": [{"kind": "window", "wall": 4, "offset": 1, "width": 0.9}]
+            }"#,
+        )
+        .unwrap();
+        let error = plan.meshes().unwrap_err().to_string();
+        assert!(error.contains("opening 0") && error.contains("wall 4"));
+        let flat = FloorPlan {
+            wall_thickness: 0.0,
+            openings: vec![],
+            ..plan
+        };
+        assert!(flat.meshes().is_err());
+    }
+
+    #[test]
+    fn svg_plan() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <path d="M 0 0 h 400 v 300 H 0 z"/>
+            <line x1="200" y1="0" x2="200" y2="300"/>
+            <line class="window wide" x1="50" y1="300" x2="150" y2="300"/>
+        </svg>"#;
+        let plan = parse_svg(svg).unwrap();
+        assert_eq!(
+            plan.outline,
+            vec![[0.0, 0.0], [4.0, 0.0], [4.0, 3.0], [0.0, 3.0]]
+        );
+        assert_eq!(plan.walls.len(), 1);
+        // on the far edge, which runs from (4, 3) back to (0, 3)
+        let Opening::Window {
+            wall,
+            offset,
+            width,
+            ..
+        } = plan.openings[0]
+        else {
+            panic!("expected a window");
+        };
+        assert_eq!(wall, 2);
+        assert!((width - 1.0).abs() < 1e-5 && (offset - 2.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,394 @@
+// Move and turn the selected object with handles drawn around it:
+// arrows move along one axis, squares within a plane, the ring turns it around the vertical.
+// Hold X, Y or Z to lock the move to that axis, Ctrl to snap.
+use crate::hover::{
+    update_clicks, update_drag_start, Drag, DragEnd, DragStart, Draggable, Dragged, MouseRay,
+    Selected,
+};
+use crate::room::RoomBounds;
+use bevy::prelude::*;
+
+const SIZE: f32 = 0.15; // handle length as a fraction of the distance to the camera
+const PICK_WIDTH: f32 = 0.08; // how close the ray must pass, as a fraction of the handle length
+const PLANE_OFFSET: f32 = 0.35; // plane squares sit this far out between their two axes
+const PLANE_HALF: f32 = 0.12;
+const RING_RADIUS: f32 = 1.2;
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct GizmoSnap {
+    pub translation: f32, // world units
+    pub yaw: f32,         // degrees
+}
+
+impl Default for GizmoSnap {
+    fn default() -> Self {
+        Self {
+            translation: 0.25,
+            yaw: 15.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GizmoHandle {
+    Axis(Vec3),
+    Plane(Vec3), // normal of the plane moved in
+    Yaw,
+}
+
+const AXES: [(Vec3, Color); 3] = [
+    (Vec3::X, Color::RED),
+    (Vec3::Y, Color::GREEN),
+    (Vec3::Z, Color::BLUE),
+];
+
+/// Parameter along the line `origin + axis * s` of the point closest to the ray
+fn closest_on_axis(origin: Vec3, axis: Vec3, ray: Ray) -> Option<(f32, f32)> {
+    let w = origin - ray.origin;
+    let b = axis.dot(ray.direction);
+    let denom = axis.length_squared() * ray.direction.length_squared() - b * b;
+    if denom.abs() < 1e-6 {
+        return None; // looking straight down the axis
+    }
+    let (d, e) = (axis.dot(w), ray.direction.dot(w));
+    let s = (b * e - ray.direction.length_squared() * d) / denom;
+    let t = (axis.length_squared() * e - b * d) / denom;
+    let gap = (origin + axis * s).distance(ray.origin + ray.direction * t);
+    Some((s, gap))
+}
+
+fn plane_hit(point: Vec3, normal: Vec3, ray: Ray) -> Option<Vec3> {
+    let t = ray.intersect_plane(point, normal)?;
+    Some(ray.get_point(t))
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+fn handle_under_ray(center: Vec3, size: f32, ray: Ray) -> Option<GizmoHandle> {
+    let width = size * PICK_WIDTH;
+    for (axis, _) in AXES {
+        if let Some((s, gap)) = closest_on_axis(center, axis, ray) {
+            if gap < width && (0.0..=size).contains(&s) {
+                return Some(GizmoHandle::Axis(axis));
+            }
+        }
+    }
+    for (normal, _) in AXES {
+        let Some(hit) = plane_hit(center, normal, ray) else {
+            continue;
+        };
+        let local = hit - center - plane_center(normal) * size;
+        if local.abs().max_element() < PLANE_HALF * size {
+            return Some(GizmoHandle::Plane(normal));
+        }
+    }
+    let hit = plane_hit(center, Vec3::Y, ray)?;
+    let radius = (hit - center).length();
+    ((radius - RING_RADIUS * size).abs() < width).then_some(GizmoHandle::Yaw)
+}
+
+/// Middle of the square for the plane with this normal, in units of the handle length
+fn plane_center(normal: Vec3) -> Vec3 {
+    (Vec3::ONE - normal.abs()) * PLANE_OFFSET
+}
+
+type SelectedDraggable = (With<Selected>, With<Draggable>);
+
+struct ActiveDrag {
+    entity: Entity,
+    handle: GizmoHandle,
+    start: Transform, // local, what gets written back
+    parent: Mat4,     // from the entity's local space to the world, identity at the top level
+    center: Vec3,     // world space
+    grab: Vec3,       // where the ray met the handle when the drag started
+    axes: [f32; 3],   // grab parameter along x, y and z through the start, for axis locks
+    start_yaw: f32,
+}
+
+#[derive(Resource, Default)]
+struct GizmoState {
+    hovered: Option<GizmoHandle>,
+    active: Option<ActiveDrag>,
+}
+
+fn gizmo_size(center: Vec3, ray: Ray) -> f32 {
+    center.distance(ray.origin).max(1.0) * SIZE
+}
+
+fn yaw_of(offset: Vec3) -> f32 {
+    offset.x.atan2(offset.z)
+}
+
+fn draw_gizmo(
+    mut gizmos: Gizmos,
+    state: Res<GizmoState>,
+    ray_query: Query<&MouseRay>,
+    selected: Query<&GlobalTransform, SelectedDraggable>,
+) {
+    let Ok(MouseRay { ray, .. }) = ray_query.get_single() else {
+        return;
+    };
+    let active = state.active.as_ref().map(|a| a.handle);
+    let highlight = |handle: GizmoHandle, color: Color| {
+        if active == Some(handle) || (active.is_none() && state.hovered == Some(handle)) {
+            Color::YELLOW
+        } else {
+            color
+        }
+    };
+    for transform in selected.iter() {
+        let center = transform.translation();
+        let size = gizmo_size(center, *ray);
+        for (axis, color) in AXES {
+            let color = highlight(GizmoHandle::Axis(axis), color);
+            gizmos.line(center, center + axis * size, color);
+        }
+        for (normal, color) in AXES {
+            let color = highlight(GizmoHandle::Plane(normal), color);
+            let mid = center + plane_center(normal) * size;
+            let mut sides = AXES.iter().map(|(a, _)| *a).filter(|a| *a != normal);
+            let (u, v) = (sides.next().unwrap(), sides.next().unwrap());
+            let (u, v) = (u * PLANE_HALF * size, v * PLANE_HALF * size);
+            gizmos.linestrip(
+                [
+                    mid + u + v,
+                    mid + u - v,
+                    mid - u - v,
+                    mid - u + v,
+                    mid + u + v,
+                ],
+                color,
+            );
+        }
+        let color = highlight(GizmoHandle::Yaw, Color::ORANGE);
+        gizmos.circle(center, Vec3::Y, RING_RADIUS * size, color);
+    }
+}
+
+fn start_gizmo_drag(
+    mut commands: Commands,
+    mut state: ResMut<GizmoState>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    ray_query: Query<&MouseRay>,
+    selected: Query<(Entity, &Transform, &GlobalTransform), SelectedDraggable>,
+    mut drag_start_events: EventWriter<DragStart>,
+) {
+    let Ok(MouseRay { ray, .. }) = ray_query.get_single() else {
+        return;
+    };
+    state.hovered = None;
+    if state.active.is_some() {
+        return;
+    }
+    for (entity, transform, global) in selected.iter() {
+        let center = global.translation();
+        let size = gizmo_size(center, *ray);
+        let Some(handle) = handle_under_ray(center, size, *ray) else {
+            continue;
+        };
+        state.hovered = Some(handle);
+        if !mouse_button_input.just_pressed(MouseButton::Left) {
+            return;
+        }
+        // the handle has the press, so it neither clicks nor drags what's behind it
+        mouse_button_input.clear_just_pressed(MouseButton::Left);
+        let grab = match handle {
+            GizmoHandle::Axis(axis) => {
+                let s = closest_on_axis(center, axis, *ray).map_or(0.0, |(s, _)| s);
+                center + axis * s
+            }
+            GizmoHandle::Plane(normal) => plane_hit(center, normal, *ray).unwrap_or(center),
+            GizmoHandle::Yaw => plane_hit(center, Vec3::Y, *ray).unwrap_or(center),
+        };
+        let axes =
+            AXES.map(|(axis, _)| closest_on_axis(center, axis, *ray).map_or(0.0, |(s, _)| s));
+        state.active = Some(ActiveDrag {
+            entity,
+            handle,
+            start: *transform,
+            parent: global.compute_matrix() * transform.compute_matrix().inverse(),
+            center,
+            grab,
+            axes,
+            start_yaw: yaw_of(grab - center),
+        });
+        // so ghosts stop following their trajectory and the highlight shows the drag
+        commands.entity(entity).insert(Dragged {
+            start_pos: transform.translation,
+            gizmo: true,
+        });
+        drag_start_events.send(DragStart {
+            entity,
+            button: MouseButton::Left,
+            hit: grab,
+        });
+        return;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gizmo_drag(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    snap_steps: Res<GizmoSnap>,
+    bounds: Res<RoomBounds>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mut state: ResMut<GizmoState>,
+    ray_query: Query<&MouseRay>,
+    mut transforms: Query<&mut Transform>,
+    mut drag_events: EventWriter<Drag>,
+    mut drag_end_events: EventWriter<DragEnd>,
+) {
+    let Some(drag) = &state.active else {
+        return;
+    };
+    let Ok(mut transform) = transforms.get_mut(drag.entity) else {
+        state.active = None;
+        return;
+    };
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        commands.entity(drag.entity).remove::<Dragged>();
+        drag_end_events.send(DragEnd {
+            entity: drag.entity,
+            button: MouseButton::Left,
+            hit: drag.parent.transform_point3(transform.translation),
+        });
+        state.active = None;
+        return;
+    }
+    let Ok(MouseRay { ray, .. }) = ray_query.get_single() else {
+        return;
+    };
+    let snapping = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let center = drag.center;
+
+    if drag.handle == GizmoHandle::Yaw {
+        let Some(hit) = plane_hit(center, Vec3::Y, *ray) else {
+            return;
+        };
+        let mut turn = (yaw_of(hit - center) - drag.start_yaw).to_degrees();
+        if snapping {
+            turn = snap(turn, snap_steps.yaw);
+        }
+        // turned about the world's up axis, whichever way the parent faces
+        let (_, parent_rotation, _) = drag.parent.to_scale_rotation_translation();
+        let turn = Quat::from_rotation_y(turn.to_radians());
+        transform.rotation =
+            parent_rotation.inverse() * turn * parent_rotation * drag.start.rotation;
+        drag_events.send(Drag {
+            entity: drag.entity,
+            button: MouseButton::Left,
+            hit,
+        });
+        return;
+    }
+
+    let locked = [KeyCode::X, KeyCode::Y, KeyCode::Z]
+        .iter()
+        .position(|key| keys.pressed(*key));
+    let offset = match (locked, drag.handle) {
+        (Some(i), _) => {
+            let axis = AXES[i].0;
+            closest_on_axis(center, axis, *ray).map(|(s, _)| axis * (s - drag.axes[i]))
+        }
+        (None, GizmoHandle::Axis(axis)) => {
+            closest_on_axis(center, axis, *ray).map(|(s, _)| center + axis * s - drag.grab)
+        }
+        (None, GizmoHandle::Plane(normal)) => plane_hit(center, normal, *ray).map(|hit| {
+            let offset = hit - drag.grab;
+            offset - normal * offset.dot(normal)
+        }),
+        (None, GizmoHandle::Yaw) => None,
+    };
+    let Some(mut offset) = offset else {
+        return;
+    };
+    if snapping {
+        offset = Vec3::new(
+            snap(offset.x, snap_steps.translation),
+            snap(offset.y, snap_steps.translation),
+            snap(offset.z, snap_steps.translation),
+        );
+    }
+    let target = bounds.clamp(center + offset);
+    transform.translation = drag.parent.inverse().transform_point3(target);
+    drag_events.send(Drag {
+        entity: drag.entity,
+        button: MouseButton::Left,
+        hit: drag.grab + offset,
+    });
+}
+
+pub struct GizmoPlugin;
+
+impl Plugin for GizmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GizmoSnap>()
+            .init_resource::<GizmoSnap>()
+            .init_resource::<GizmoState>()
+            .add_systems(
+                Update,
+                start_gizmo_drag
+                    .after(crate::hover::pick)
+                    .after(crate::path::edit_path)
+                    .before(update_clicks)
+                    .before(update_drag_start),
+            )
+            .add_systems(Update, gizmo_drag.after(start_gizmo_drag))
+            .add_systems(PostUpdate, draw_gizmo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_point_on_axis() {
+        // ray straight down through x = 3
+        let ray = Ray {
+            origin: Vec3::new(3.0, 10.0, 0.0),
+            direction: Vec3::NEG_Y,
+        };
+        let (s, gap) = closest_on_axis(Vec3::ZERO, Vec3::X, ray).unwrap();
+        assert!((s - 3.0).abs() < 1e-5 && gap.abs() < 1e-5);
+        // looking down the y axis itself gives nothing
+        assert!(closest_on_axis(Vec3::ZERO, Vec3::Y, ray).is_none());
+    }
+
+    #[test]
+    fn picks_handles() {
+        let down = |x: f32, z: f32| Ray {
+            origin: Vec3::new(x, 10.0, z),
+            direction: Vec3::NEG_Y,
+        };
+        let size = 1.0;
+        assert_eq!(
+            handle_under_ray(Vec3::ZERO, size, down(0.5, 0.0)),
+            Some(GizmoHandle::Axis(Vec3::X))
+        );
+        assert_eq!(
+            handle_under_ray(Vec3::ZERO, size, down(PLANE_OFFSET, PLANE_OFFSET)),
+            Some(GizmoHandle::Plane(Vec3::Y))
+        );
+        assert_eq!(
+            handle_under_ray(Vec3::ZERO, size, down(0.0, -RING_RADIUS)),
+            Some(GizmoHandle::Yaw)
+        );
+        assert_eq!(handle_under_ray(Vec3::ZERO, size, down(-0.6, -0.6)), None);
+    }
+
+    #[test]
+    fn snapping() {
+        assert_eq!(snap(0.37, 0.25), 0.25);
+        assert_eq!(snap(-0.4, 0.25), -0.5);
+        assert_eq!(snap(0.37, 0.0), 0.37);
+    }
+}
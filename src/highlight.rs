@@ -0,0 +1,195 @@
+// Tints hovered, selected and dragged models with a see-through overlay,
+// so their own materials are never swapped out or shared
+use crate::hover::{hierarchy, Dragged, Hover, Selected};
+use bevy::ecs::query::Has;
+use bevy::pbr::{NotShadowCaster, NotShadowReceiver};
+use bevy::prelude::*;
+
+/// Copy of a mesh drawn over the original, skipped by picking
+#[derive(Component)]
+pub struct HighlightOverlay;
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct HighlightColors {
+    pub hover: Color,
+    pub selected: Color,
+    pub dragging: Color,
+}
+
+impl Default for HighlightColors {
+    fn default() -> Self {
+        Self {
+            hover: Color::rgba(1.0, 0.0, 0.0, 0.35),
+            selected: Color::rgba(1.0, 0.8, 0.0, 0.3),
+            dragging: Color::rgba(0.2, 0.6, 1.0, 0.4),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HighlightState {
+    Hover,
+    Selected,
+    Dragging,
+}
+
+/// On a highlighted root, with the overlays spawned for its parts
+#[derive(Component)]
+struct Highlighted {
+    state: HighlightState,
+    overlays: Vec<Entity>,
+}
+
+#[derive(Resource)]
+struct HighlightMaterials {
+    hover: Handle<StandardMaterial>,
+    selected: Handle<StandardMaterial>,
+    dragging: Handle<StandardMaterial>,
+}
+
+impl HighlightMaterials {
+    fn get(&self, state: HighlightState) -> Handle<StandardMaterial> {
+        match state {
+            HighlightState::Hover => self.hover.clone(),
+            HighlightState::Selected => self.selected.clone(),
+            HighlightState::Dragging => self.dragging.clone(),
+        }
+    }
+}
+
+fn overlay_material(color: Color) -> StandardMaterial {
+    StandardMaterial {
+        base_color: color,
+        unlit: true,
+        alpha_mode: AlphaMode::Blend,
+        depth_bias: 1.0, // drawn on top of the coplanar original
+        ..default()
+    }
+}
+
+fn add_materials(
+    mut commands: Commands,
+    colors: Res<HighlightColors>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(HighlightMaterials {
+        hover: materials.add(overlay_material(colors.hover)),
+        selected: materials.add(overlay_material(colors.selected)),
+        dragging: materials.add(overlay_material(colors.dragging)),
+    });
+}
+
+fn sync_colors(
+    colors: Res<HighlightColors>,
+    handles: Res<HighlightMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !colors.is_changed() {
+        return;
+    }
+    for (handle, color) in [
+        (&handles.hover, colors.hover),
+        (&handles.selected, colors.selected),
+        (&handles.dragging, colors.dragging),
+    ] {
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color = color;
+        }
+    }
+}
+
+type HighlightCandidate<'a> = (
+    Entity,
+    Has<Hover>,
+    Has<Selected>,
+    Has<Dragged>,
+    Option<&'a mut Highlighted>,
+);
+
+type HighlightFilter = Or<(
+    With<Hover>,
+    With<Selected>,
+    With<Dragged>,
+    With<Highlighted>,
+)>;
+
+fn update_highlights(
+    mut commands: Commands,
+    materials: Res<HighlightMaterials>,
+    mut candidates: Query<HighlightCandidate, HighlightFilter>,
+    children: Query<&Children>,
+    meshes: Query<(&Handle<Mesh>, &GlobalTransform), Without<HighlightOverlay>>,
+) {
+    for (entity, hover, selected, dragged, highlighted) in candidates.iter_mut() {
+        let desired = if dragged {
+            Some(HighlightState::Dragging)
+        } else if selected {
+            Some(HighlightState::Selected)
+        } else if hover {
+            Some(HighlightState::Hover)
+        } else {
+            None
+        };
+
+        match (desired, highlighted) {
+            (Some(state), Some(mut highlighted)) if highlighted.state != state => {
+                // same overlays, different tint
+                for overlay in &highlighted.overlays {
+                    if let Some(mut overlay) = commands.get_entity(*overlay) {
+                        overlay.insert(materials.get(state));
+                    }
+                }
+                highlighted.state = state;
+            }
+            (Some(state), None) => {
+                let overlays = hierarchy(entity, &children)
+                    .into_iter()
+                    .filter_map(|part| {
+                        let (mesh, global) = meshes.get(part).ok()?;
+                        let overlay = commands
+                            .spawn((
+                                PbrBundle {
+                                    mesh: mesh.clone(),
+                                    material: materials.get(state),
+                                    // spawned after this frame's propagation, so start where the part is
+                                    global_transform: *global,
+                                    ..default()
+                                },
+                                HighlightOverlay,
+                                NotShadowCaster,
+                                NotShadowReceiver,
+                            ))
+                            .id();
+                        commands.entity(part).add_child(overlay);
+                        Some(overlay)
+                    })
+                    .collect();
+                commands
+                    .entity(entity)
+                    .insert(Highlighted { state, overlays });
+            }
+            (None, Some(highlighted)) => {
+                for overlay in &highlighted.overlays {
+                    if let Some(overlay) = commands.get_entity(*overlay) {
+                        overlay.despawn_recursive();
+                    }
+                }
+                commands.entity(entity).remove::<Highlighted>();
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct HighlightPlugin;
+
+impl Plugin for HighlightPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<HighlightColors>()
+            .init_resource::<HighlightColors>()
+            .add_systems(Startup, add_materials)
+            .add_systems(Update, sync_colors)
+            .add_systems(PostUpdate, update_highlights);
+    }
+}
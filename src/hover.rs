@@ -1,171 +1,486 @@
 use bevy::prelude::*;
 pub struct DraggablePlugin;
 
+use crate::bvh::MeshBvhs;
+use crate::highlight::HighlightOverlay;
+use crate::path::PathEditor;
+use crate::room::{RoomBounds, RoomGeometry};
 use bevy::prelude::*;
-use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::camera::NormalizedRenderTarget;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::{EguiContexts, EguiSet};
 use std::collections::HashMap;
 
 #[derive(Component)]
 pub struct Hoverable;
 
+/// On the entity currently under the cursor
 #[derive(Component)]
-struct Hover;
+pub struct Hover;
 
 #[derive(Component, Default)]
-struct MouseRay {
-    ray: Ray,
+pub struct MouseRay {
+    pub ray: Ray,
+    cursor: Option<(Entity, Vec2)>, // window and position the ray was last cast through
 }
 #[derive(Component)]
 pub struct MouseRaySource;
 
-#[derive(Resource)]
-struct HoverMaterial(Handle<StandardMaterial>);
+impl MouseRay {
+    /// Ray through `position` (logical pixels, y down) in `window`, cast from the topmost
+    /// `MouseRaySource` camera whose viewport contains it.
+    /// Works for perspective and orthographic projections alike.
+    pub fn from_window(
+        window: Entity,
+        position: Vec2,
+        primary_window: Option<Entity>,
+        cameras: &Query<(&Camera, &GlobalTransform), With<MouseRaySource>>,
+    ) -> Option<Ray> {
+        let mut cameras: Vec<_> = cameras
+            .iter()
+            .filter(|(camera, _)| camera.is_active)
+            .collect();
+        cameras.sort_by_key(|(camera, _)| std::cmp::Reverse(camera.order));
+        cameras.into_iter().find_map(|(camera, transform)| {
+            match camera.target.normalize(primary_window)? {
+                NormalizedRenderTarget::Window(w) if w.entity() == window => {}
+                _ => return None,
+            }
+            let viewport = camera.logical_viewport_rect()?;
+            if !viewport.contains(position) {
+                return None;
+            }
+            camera.viewport_to_world(transform, position - viewport.min)
+        })
+    }
 
-#[derive(Resource)]
-struct HoverMaterialStore(HashMap<Entity, Handle<StandardMaterial>>);
+    /// Window and position (logical pixels) the ray is cast through
+    pub fn cursor(&self) -> Option<(Entity, Vec2)> {
+        self.cursor
+    }
 
-impl MouseRay {
-    pub fn cursor_to_pos(position: &Vec2, window: &Window) -> Vec2 {
-        let (window_width, window_height) = (window.width(), window.height());
-        Vec2::new(
-            position.x / window_width * 2.0 - 1.0,
-            // cursor_pos is from a `winit::CursorMoved` event
-            // where positive x goes right and positive y goes **down**
-            // see https://docs.rs/winit/latest/winit/event/enum.WindowEvent.html#variant.CursorMoved
-            // in bevy, positive y goes **up**
-            // flip y to convert
-            1.0 - (position.y / window_height * 2.0),
-        )
-    }
-
-    pub fn pos_from_camera(
-        camera: &Camera,
-        transform: &GlobalTransform,
-        cursor_pos: Vec2, // [-1, 1]
-    ) -> Ray {
-        let clip_space_pos = Vec3::new(cursor_pos.x, cursor_pos.y, 0.0);
-        let inverse_projection = camera.projection_matrix().inverse();
-        let eye_space_pos = inverse_projection.transform_point3(clip_space_pos);
-        let world_space_pos = transform.compute_matrix() * eye_space_pos.extend(1.0);
-
-        Ray {
-            origin: transform.translation(),
-            direction: (world_space_pos.truncate() - transform.translation()).normalize(),
-        }
+    /// Aim the ray at a position without a `CursorMoved`, e.g. a finger on a touch screen
+    pub fn point_at(&mut self, window: Entity, position: Vec2) {
+        self.cursor = Some((window, position));
     }
 }
 
 #[derive(Component)]
 pub struct Draggable;
 
+/// On the entity picked by the last click, Escape clears it
+#[derive(Component)]
+pub struct Selected;
+
+/// On entities being dragged with the left mouse button
 #[derive(Component)]
-struct Dragged {
-    start_pos: Vec3,
+pub struct Dragged {
+    pub start_pos: Vec3,
+    pub gizmo: bool, // moved by a gizmo handle, which ends the drag itself, not by following the cursor
 }
 
-fn add_mouse_ray(mut commands: Commands) {
-    commands.spawn(MouseRay::default());
+// Pointer events, so other plugins can react to the cursor without doing their own ray casts.
+// `hit` is the world space point under the cursor.
+
+#[derive(Event, Clone, Debug)]
+pub struct PointerOver {
+    pub entity: Entity,
+    pub hit: Vec3,
 }
 
-fn add_materials(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
-    let hover_material = materials.add(StandardMaterial {
-        base_color: Color::RED,
-        ..Default::default()
-    });
+#[derive(Event, Clone, Debug)]
+pub struct PointerOut {
+    pub entity: Entity,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct Click {
+    pub entity: Entity,
+    pub button: MouseButton,
+    pub hit: Vec3,
+}
 
-    commands.insert_resource(HoverMaterial(hover_material));
-    commands.insert_resource(HoverMaterialStore(HashMap::new()));
+#[derive(Event, Clone, Debug)]
+pub struct DoubleClick {
+    pub entity: Entity,
+    pub button: MouseButton,
+    pub hit: Vec3,
 }
 
-fn update_mouse_ray(
+#[derive(Event, Clone, Debug)]
+pub struct DragStart {
+    pub entity: Entity,
+    pub button: MouseButton,
+    pub hit: Vec3,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct Drag {
+    pub entity: Entity,
+    pub button: MouseButton,
+    pub hit: Vec3, // where the cursor meets the drag plane
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct DragEnd {
+    pub entity: Entity,
+    pub button: MouseButton,
+    pub hit: Vec3,
+}
+
+/// Whether an egui text field has the keyboard, updated at the start of every frame
+#[derive(Resource, Default)]
+pub struct EguiKeyboard(bool);
+
+fn track_egui_keyboard(mut contexts: EguiContexts, mut keyboard: ResMut<EguiKeyboard>) {
+    keyboard.0 = contexts.ctx_mut().wants_keyboard_input();
+}
+
+/// Run condition for hotkeys, `.run_if(not(egui_wants_keyboard))` keeps them quiet while
+/// something is being typed into an egui text field
+pub fn egui_wants_keyboard(keyboard: Res<EguiKeyboard>) -> bool {
+    keyboard.0
+}
+
+/// Print every pointer event, flip it on in the inspector when debugging input
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct PointerLog(pub bool);
+
+#[allow(clippy::too_many_arguments)]
+fn log_pointer_events(
+    log: Res<PointerLog>,
+    mut over: EventReader<PointerOver>,
+    mut out: EventReader<PointerOut>,
+    mut click: EventReader<Click>,
+    mut double_click: EventReader<DoubleClick>,
+    mut drag_start: EventReader<DragStart>,
+    mut drag: EventReader<Drag>,
+    mut drag_end: EventReader<DragEnd>,
+) {
+    if !log.0 {
+        return;
+    }
+    for e in over.iter() {
+        println!("pointer over {:?} at {}", e.entity, e.hit);
+    }
+    for e in out.iter() {
+        println!("pointer out {:?}", e.entity);
+    }
+    for e in click.iter() {
+        println!("click {:?} {:?} at {}", e.entity, e.button, e.hit);
+    }
+    for e in double_click.iter() {
+        println!("double click {:?} {:?} at {}", e.entity, e.button, e.hit);
+    }
+    for e in drag_start.iter() {
+        println!("drag start {:?} {:?} at {}", e.entity, e.button, e.hit);
+    }
+    for e in drag.iter() {
+        println!("drag {:?} {:?} to {}", e.entity, e.button, e.hit);
+    }
+    for e in drag_end.iter() {
+        println!("drag end {:?} {:?} at {}", e.entity, e.button, e.hit);
+    }
+}
+
+const CLICK_SLOP: f32 = 5.0; // pixels the cursor may move between press and release
+const DOUBLE_CLICK_TIME: f32 = 0.35; // seconds
+
+struct Press {
+    entity: Option<Entity>,
+    cursor: Vec2,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct PointerState {
+    over: Option<Entity>,
+    pressed: HashMap<MouseButton, Press>,
+    last_click: Option<(Entity, MouseButton, f32)>,
+}
+
+impl PointerState {
+    fn press(&mut self, button: MouseButton, entity: Option<Entity>, cursor: Vec2) {
+        self.pressed.insert(button, Press { entity, cursor });
+    }
+
+    /// `Some(double)` if releasing `button` over `entity` completes a click
+    fn release(
+        &mut self,
+        button: MouseButton,
+        entity: Option<Entity>,
+        cursor: Vec2,
+        now: f32,
+    ) -> Option<bool> {
+        let press = self.pressed.remove(&button)?;
+        let entity = entity.filter(|e| press.entity == Some(*e))?;
+        if press.cursor.distance(cursor) > CLICK_SLOP {
+            return None;
+        }
+        match self.last_click {
+            Some((last, last_button, at))
+                if last == entity && last_button == button && now - at < DOUBLE_CLICK_TIME =>
+            {
+                self.last_click = None; // a third click starts over
+                Some(true)
+            }
+            _ => {
+                self.last_click = Some((entity, button, now));
+                Some(false)
+            }
+        }
+    }
+}
+
+fn add_mouse_ray(mut commands: Commands) {
+    commands.spawn(MouseRay::default());
+}
+
+pub(crate) fn update_mouse_ray(
     mut query: Query<&mut MouseRay>,
-    windows: Query<&Window>,
+    primary_window: Query<Entity, With<PrimaryWindow>>,
     mut cursor_moved_events: EventReader<CursorMoved>,
-    camera_query: Query<(&Camera, &GlobalTransform)>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MouseRaySource>>,
 ) {
-    if let (Ok(window), Ok(mut mouse_ray)) = (windows.get_single(), query.get_single_mut()) {
-        for event in cursor_moved_events.iter() {
-            let (camera, camera_transform) = camera_query.single();
-            let cursor_pos = MouseRay::cursor_to_pos(&event.position, window);
-            let ray = MouseRay::pos_from_camera(camera, camera_transform, cursor_pos);
-            mouse_ray.ray = ray;
+    let Ok(mut mouse_ray) = query.get_single_mut() else {
+        return;
+    };
+    if let Some(event) = cursor_moved_events.iter().last() {
+        mouse_ray.cursor = Some((event.window, event.position));
+    }
+    // recast every frame, the camera may have moved under a still cursor
+    let Some((window, position)) = mouse_ray.cursor else {
+        return;
+    };
+    if let Some(ray) = MouseRay::from_window(
+        window,
+        position,
+        primary_window.get_single().ok(),
+        &camera_query,
+    ) {
+        mouse_ray.ray = ray;
+    }
+}
+
+/// Closest `Hoverable` under the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pick {
+    pub entity: Entity, // the `Hoverable`, may be an ancestor of the mesh that was hit
+    pub mesh: Entity,
+    pub point: Vec3,  // world space
+    pub normal: Vec3, // world space, facing back towards the camera
+    pub distance: f32,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct PickResult(pub Option<Pick>);
+
+/// Nearest ancestor (or the entity itself) that is `Hoverable`
+fn hoverable_owner(
+    mut entity: Entity,
+    parents: &Query<&Parent>,
+    hoverables: &Query<Entity, With<Hoverable>>,
+) -> Option<Entity> {
+    loop {
+        if hoverables.contains(entity) {
+            return Some(entity);
         }
+        entity = parents.get(entity).ok()?.get();
     }
 }
 
-fn update_hover_start(
-    mut commands: Commands,
-    mesh_assets: Res<Assets<Mesh>>,
-    hover_material: ResMut<HoverMaterial>,
-    mut hover_material_store: ResMut<HoverMaterialStore>,
+/// `root` and everything below it, multi-part models are picked and highlighted as one
+pub fn hierarchy(root: Entity, children: &Query<&Children>) -> Vec<Entity> {
+    std::iter::once(root)
+        .chain(children.iter_descendants(root))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn pick(
+    bvhs: Res<MeshBvhs>,
     ray_query: Query<&MouseRay>,
-    query: Query<
-        (
-            &Handle<Mesh>,
-            &Handle<StandardMaterial>,
-            &GlobalTransform,
-            &Hoverable,
-            Entity,
-        ),
-        Without<Hover>,
+    hoverables: Query<Entity, With<Hoverable>>,
+    children: Query<&Children>,
+    parents: Query<&Parent>,
+    meshes: Query<
+        (&Handle<Mesh>, &GlobalTransform, &ComputedVisibility),
+        Without<HighlightOverlay>,
     >,
+    geometry: RoomGeometry,
+    editor: Res<PathEditor>,
+    mut result: ResMut<PickResult>,
 ) {
-    for ray in ray_query.iter() {
-        for (mesh_handle, material_handle, transform, _, entity) in query.iter() {
-            if let Some(mesh) = mesh_assets.get(mesh_handle) {
-                if check_intersect(ray, mesh, transform) {
-                    //println!("Intersected {:?}", entity);
-                    commands.entity(entity).insert(Hover {});
-
-                    hover_material_store
-                        .0
-                        .insert(entity, material_handle.clone());
-
-                    commands.entity(entity).insert(hover_material.0.clone());
-                }
+    let Ok(MouseRay { ray, .. }) = ray_query.get_single() else {
+        return;
+    };
+    // nothing to hover or grab while clicks are placing path points
+    if editor.recording() {
+        result.0 = None;
+        return;
+    }
+    // walls that are drawn hide what's behind them, cut away ones don't
+    let wall = geometry.hit(*ray).map(|hit| hit.point.distance(ray.origin));
+    let closest = hoverables
+        .iter()
+        // nested hoverables get visited from their outermost ancestor
+        .filter(|root| {
+            parents.get(*root).map_or(true, |p| {
+                hoverable_owner(p.get(), &parents, &hoverables).is_none()
+            })
+        })
+        .flat_map(|root| hierarchy(root, &children))
+        .filter_map(|mesh| {
+            let (mesh_handle, transform, visibility) = meshes.get(mesh).ok()?;
+            // lamps on hidden floors can't be grabbed
+            if !visibility.is_visible_in_hierarchy() {
+                return None;
             }
-        }
+            let hit = bvhs.get(mesh_handle)?.intersect_world(*ray, transform)?;
+            Some((mesh, transform, hit))
+        })
+        .min_by(|(_, _, a), (_, _, b)| a.t.total_cmp(&b.t))
+        .filter(|(_, _, hit)| wall.is_none_or(|wall| hit.t <= wall));
+
+    result.0 = closest.and_then(|(mesh, transform, hit)| {
+        let [v0, v1, v2] = hit.triangle.map(|v| transform.transform_point(v));
+        let normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+        Some(Pick {
+            entity: hoverable_owner(mesh, &parents, &hoverables)?,
+            mesh,
+            point: ray.origin + ray.direction * hit.t,
+            normal: if normal.dot(ray.direction) > 0.0 {
+                -normal
+            } else {
+                normal
+            },
+            distance: hit.t,
+        })
+    });
+}
+
+fn update_hover(
+    mut commands: Commands,
+    pick: Res<PickResult>,
+    hovered: Query<Entity, With<Hover>>,
+) {
+    let target = pick.0.map(|p| p.entity);
+    for entity in hovered.iter().filter(|e| Some(*e) != target) {
+        commands.entity(entity).remove::<Hover>();
+    }
+    if let Some(entity) = target.filter(|e| !hovered.contains(*e)) {
+        commands.entity(entity).insert(Hover {});
+    }
+}
+
+fn update_selection(
+    mut commands: Commands,
+    mut click_events: EventReader<Click>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    let Some(clicked) = click_events
+        .iter()
+        .filter(|e| e.button == MouseButton::Left)
+        .last()
+        .map(|e| e.entity)
+    else {
+        return;
+    };
+    for entity in selected.iter().filter(|e| *e != clicked) {
+        commands.entity(entity).remove::<Selected>();
     }
+    commands.entity(clicked).insert(Selected);
 }
 
-fn update_hover_end(
+fn clear_selection(
     mut commands: Commands,
-    mesh_assets: Res<Assets<Mesh>>,
-    mut hover_material_store: ResMut<HoverMaterialStore>,
+    keys: Res<Input<KeyCode>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        for entity in selected.iter() {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+}
+
+fn update_pointer_over(
+    pick: Res<PickResult>,
+    mut state: ResMut<PointerState>,
+    mut over_events: EventWriter<PointerOver>,
+    mut out_events: EventWriter<PointerOut>,
+) {
+    let current = pick.0.map(|p| p.entity);
+    if current == state.over {
+        return;
+    }
+    if let Some(entity) = state.over {
+        out_events.send(PointerOut { entity });
+    }
+    if let Some(p) = pick.0 {
+        over_events.send(PointerOver {
+            entity: p.entity,
+            hit: p.point,
+        });
+    }
+    state.over = current;
+}
+
+pub(crate) fn update_clicks(
+    time: Res<Time>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    pick: Res<PickResult>,
     ray_query: Query<&MouseRay>,
-    query: Query<
-        (
-            &Handle<Mesh>,
-            &Handle<StandardMaterial>,
-            &GlobalTransform,
-            Entity,
-        ),
-        With<Hover>,
-    >,
+    mut state: ResMut<PointerState>,
+    mut click_events: EventWriter<Click>,
+    mut double_click_events: EventWriter<DoubleClick>,
 ) {
-    for ray in ray_query.iter() {
-        for (mesh_handle, _material_handle, transform, entity) in query.iter() {
-            if let Some(mesh) = mesh_assets.get(mesh_handle) {
-                if !check_intersect(ray, mesh, transform) {
-                    if let Some(original_material_handle) = hover_material_store.0.remove(&entity) {
-                        commands.entity(entity).insert(original_material_handle);
-                    }
-                    commands.entity(entity).remove::<Hover>();
-                }
-            }
+    let cursor = ray_query
+        .get_single()
+        .ok()
+        .and_then(|r| r.cursor())
+        .map_or(Vec2::ZERO, |(_, position)| position);
+    let current = pick.0.map(|p| p.entity);
+    for button in mouse_button_input.get_just_pressed() {
+        state.press(*button, current, cursor);
+    }
+    for button in mouse_button_input.get_just_released() {
+        let now = time.elapsed_seconds();
+        let (Some(double), Some(p)) = (state.release(*button, current, cursor, now), pick.0) else {
+            continue;
+        };
+        click_events.send(Click {
+            entity: p.entity,
+            button: *button,
+            hit: p.point,
+        });
+        if double {
+            double_click_events.send(DoubleClick {
+                entity: p.entity,
+                button: *button,
+                hit: p.point,
+            });
         }
     }
 }
 
-fn update_drag_start(
+pub(crate) fn update_drag_start(
     mut commands: Commands,
     mouse_button_input: Res<Input<MouseButton>>,
+    pick: Res<PickResult>,
     query: Query<(Entity, &Transform), (With<Hover>, With<Draggable>)>,
+    mut drag_start_events: EventWriter<DragStart>,
 ) {
     for (entity, transform) in &query {
         if mouse_button_input.just_pressed(MouseButton::Left) {
             commands.entity(entity).insert(Dragged {
                 start_pos: transform.translation,
+                gizmo: false,
+            });
+            drag_start_events.send(DragStart {
+                entity,
+                button: MouseButton::Left,
+                hit: pick.0.map_or(transform.translation, |p| p.point),
             });
         }
     }
@@ -174,18 +489,32 @@ fn update_drag_start(
 fn update_drag_end(
     mut commands: Commands,
     mouse_button_input: Res<Input<MouseButton>>,
-    query: Query<Entity, With<Dragged>>,
+    query: Query<(Entity, &Transform, &Dragged)>,
+    mut drag_end_events: EventWriter<DragEnd>,
 ) {
-    for entity in &query {
-        if mouse_button_input.just_released(MouseButton::Left) {
+    for (entity, transform, dragged) in &query {
+        if mouse_button_input.just_released(MouseButton::Left) && !dragged.gizmo {
             commands.entity(entity).remove::<Dragged>();
+            drag_end_events.send(DragEnd {
+                entity,
+                button: MouseButton::Left,
+                hit: transform.translation,
+            });
         }
     }
 }
 
-fn drag_system(mut query: Query<(&mut Transform, &Dragged)>, ray_query: Query<&MouseRay>) {
-    for MouseRay { ray } in ray_query.iter() {
-        for (mut transform, dragged) in query.iter_mut() {
+pub(crate) fn drag_system(
+    bounds: Res<RoomBounds>,
+    mut query: Query<(Entity, &mut Transform, &Dragged)>,
+    ray_query: Query<&MouseRay>,
+    mut drag_events: EventWriter<Drag>,
+) {
+    for MouseRay { ray, .. } in ray_query.iter() {
+        for (entity, mut transform, dragged) in query.iter_mut() {
+            if dragged.gizmo {
+                continue;
+            }
             // Define the y-coordinate of the plane
             let plane_y = dragged.start_pos.y; // Change this value as needed
 
@@ -206,47 +535,16 @@ fn drag_system(mut query: Query<(&mut Transform, &Dragged)>, ray_query: Query<&M
                 );
 
                 // clamp to avoid placing objects outside of the room
-                transform.translation.x = (dragged.start_pos.x + offset.x).clamp(-20.0, 0.0);
-                transform.translation.z = (dragged.start_pos.z + offset.z).clamp(-20.0, 0.0)
-            }
-        }
-    }
-}
-
-fn check_intersect(ray: &MouseRay, mesh: &Mesh, transform: &GlobalTransform) -> bool {
-    if let Some(VertexAttributeValues::Float32x3(vertex_positions)) =
-        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
-    {
-        let inner_fn = |indices: &Vec<u32>| {
-            for tri in indices.chunks_exact(3) {
-                let v0 = Vec3::from(vertex_positions[tri[0] as usize]);
-                let v1 = Vec3::from(vertex_positions[tri[1] as usize]);
-                let v2 = Vec3::from(vertex_positions[tri[2] as usize]);
-
-                // Transform the vertices from model space to world space
-                let mat = transform.compute_matrix();
-                let v0 = mat.transform_point3(v0);
-                let v1 = mat.transform_point3(v1);
-                let v2 = mat.transform_point3(v2);
-
-                // Use Moller-Trumbore algorithm here to check for intersection
-                if moller_trumbore(ray.ray.origin, ray.ray.direction, v0, v1, v2).is_some() {
-                    return true;
-                }
-            }
-            false
-        };
-
-        match mesh.indices() {
-            Some(bevy::render::mesh::Indices::U32(indices)) => inner_fn(indices),
-            // TODO: very bad, clones mesh so I can avoid copy-pasting inner_fn
-            Some(bevy::render::mesh::Indices::U16(indices)) => {
-                inner_fn(&indices.iter().map(|x| *x as u32).collect())
+                let target = bounds.clamp(dragged.start_pos + offset);
+                transform.translation.x = target.x;
+                transform.translation.z = target.z;
+                drag_events.send(Drag {
+                    entity,
+                    button: MouseButton::Left,
+                    hit: intersection_point,
+                });
             }
-            None => false,
         }
-    } else {
-        false
     }
 }
 
@@ -300,13 +598,85 @@ pub struct MouseRayPlugin;
 
 impl Plugin for MouseRayPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, add_mouse_ray)
-            .add_systems(Startup, add_materials)
+        app.add_plugins(crate::bvh::BvhPlugin)
+            .add_systems(Startup, add_mouse_ray)
+            .init_resource::<PickResult>()
+            .init_resource::<PointerState>()
+            .init_resource::<EguiKeyboard>()
+            .add_systems(PreUpdate, track_egui_keyboard.after(EguiSet::BeginFrame))
+            .register_type::<PointerLog>()
+            .init_resource::<PointerLog>()
+            .add_event::<PointerOver>()
+            .add_event::<PointerOut>()
+            .add_event::<Click>()
+            .add_event::<DoubleClick>()
+            .add_event::<DragStart>()
+            .add_event::<Drag>()
+            .add_event::<DragEnd>()
             .add_systems(Update, update_mouse_ray)
-            .add_systems(Update, update_hover_start)
-            .add_systems(Update, update_hover_end)
-            .add_systems(Update, update_drag_start)
+            .add_systems(Update, pick.after(update_mouse_ray))
+            .add_systems(Update, update_hover.after(pick))
+            .add_systems(Update, update_pointer_over.after(pick))
+            .add_systems(Update, update_clicks.after(pick))
+            .add_systems(Update, update_selection.after(update_clicks))
+            .add_systems(
+                Update,
+                clear_selection
+                    .after(update_selection)
+                    .run_if(not(egui_wants_keyboard)),
+            )
+            .add_systems(Update, update_drag_start.after(update_hover))
             .add_systems(Update, update_drag_end)
-            .add_systems(Update, drag_system);
+            .add_systems(Update, drag_system)
+            .add_systems(PostUpdate, log_pointer_events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(state: &mut PointerState, entity: Entity, now: f32) -> Option<bool> {
+        state.press(MouseButton::Left, Some(entity), Vec2::ZERO);
+        state.release(MouseButton::Left, Some(entity), Vec2::ONE, now)
+    }
+
+    #[test]
+    fn moving_too_far_is_not_a_click() {
+        let (mut state, lamp) = (PointerState::default(), Entity::from_raw(1));
+        state.press(MouseButton::Left, Some(lamp), Vec2::ZERO);
+        let released = Vec2::new(CLICK_SLOP + 1.0, 0.0);
+        assert_eq!(
+            state.release(MouseButton::Left, Some(lamp), released, 0.0),
+            None
+        );
+        assert_eq!(click(&mut state, lamp, 0.1), Some(false));
+    }
+
+    #[test]
+    fn double_click_on_the_same_entity() {
+        let (mut state, lamp) = (PointerState::default(), Entity::from_raw(1));
+        assert_eq!(click(&mut state, lamp, 1.0), Some(false));
+        assert_eq!(click(&mut state, lamp, 1.2), Some(true));
+        // a third click starts over, and a slow one is just a click
+        assert_eq!(click(&mut state, lamp, 1.3), Some(false));
+        assert_eq!(
+            click(&mut state, lamp, 1.3 + DOUBLE_CLICK_TIME),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn no_double_click_across_entities() {
+        let mut state = PointerState::default();
+        let (lamp, ghost) = (Entity::from_raw(1), Entity::from_raw(2));
+        assert_eq!(click(&mut state, lamp, 1.0), Some(false));
+        assert_eq!(click(&mut state, ghost, 1.2), Some(false));
+        // pressed on one, released on the other
+        state.press(MouseButton::Left, Some(lamp), Vec2::ZERO);
+        assert_eq!(
+            state.release(MouseButton::Left, Some(ghost), Vec2::ZERO, 1.3),
+            None
+        );
     }
 }
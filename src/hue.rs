@@ -9,6 +9,9 @@ use std::sync::{Arc, Mutex};
 use std::{thread, time::Duration};
 use crate::util::MapRange;
 
+// a 60W equivalent bulb, for bridges that don't report `maxlumen`
+pub const DEFAULT_LUMENS: u32 = 800;
+
 struct Conn {
     url_base: String,
     mode: ConnMode,
@@ -28,10 +31,23 @@ struct JsonState {
     // Other fields are omitted for brevity
 }
 
+#[derive(Serialize, Deserialize, Default)]
+struct JsonControl {
+    maxlumen: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct JsonCapabilities {
+    #[serde(default)]
+    control: JsonControl,
+}
+
 #[derive(Serialize, Deserialize)]
 struct JsonBulb {
     state: JsonState,
     uniqueid: String,
+    #[serde(default)]
+    capabilities: JsonCapabilities,
     // Other fields are omitted for brevity
 }
 
@@ -47,6 +63,7 @@ fn parse_state(json_str: &str) -> Result<Vec<BulbRead>, Error> {
                 idx: idx.parse::<u8>().unwrap_or_default(),
                 uuid: bulb.uniqueid.clone(),
                 on: bulb.state.on,
+                maxlumen: bulb.capabilities.control.maxlumen,
             });
         }
     }
@@ -67,11 +84,12 @@ impl Conn {
         }
     }
 
-    pub fn set_bulb_state(&self, idx: u8, brightness: f64, hue: f64) -> Result<(), Error> {
+    // takes the whole write so brightness and hue can't be passed in the wrong order
+    pub fn set_bulb_state(&self, idx: u8, write: &BulbWrite) -> Result<(), Error> {
         let url_base = &self.url_base; // can't have . in {} yet
         let url_state = format!("{url_base}/lights/{idx}/state");
-        let brightness = brightness.map((0f64, 1f64), (0f64, 255f64)) as u8;
-        let hue = hue.map((0f64, 1f64), (0f64, u16::MAX as f64)) as u16;
+        let brightness = write.brightness.map((0f64, 1f64), (0f64, 255f64)) as u8;
+        let hue = write.hue.map((0f64, 1f64), (0f64, u16::MAX as f64)) as u16;
         let body = format!(r#"{{"bri": {brightness}, "hue": {hue}}}"#);
         match self.mode {
             ConnMode::ReadWrite => {
@@ -84,6 +102,22 @@ impl Conn {
         Ok(())
     }
 
+    /// Blink the bulb once so it can be found in the room
+    pub fn alert(&self, idx: u8) -> Result<(), Error> {
+        let url_base = &self.url_base;
+        let url_state = format!("{url_base}/lights/{idx}/state");
+        let body = r#"{"alert": "select"}"#;
+        match self.mode {
+            ConnMode::ReadWrite => {
+                self.client.put(url_state).body(body).send()?;
+            }
+            _ => {
+                println!("egress: {}", body)
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_state(&self) -> Result<Vec<BulbRead>, Error> {
         let url_base = &self.url_base;
         let url_lights = format!("{url_base}/lights");
@@ -113,6 +147,7 @@ struct State {
     writes: Vec<BulbWrite>,
     reads: Vec<BulbRead>,
     brightness: u8,
+    identify: Vec<u8>, // bulbs to blink, drained by the background task
 }
 
 #[derive(Clone, Default, PartialEq)]
@@ -129,6 +164,7 @@ pub struct BulbRead {
     pub idx: u8,
     pub uuid: String,
     pub on: bool,
+    pub maxlumen: Option<u32>, // luminous flux at full brightness
     //TODO: position in space
 }
 
@@ -144,6 +180,35 @@ impl BulbState {
         }
     }
 
+    pub fn set_hue(&self, idx: u8, hue: f64) {
+        let mut state = self.inner.lock().unwrap();
+        if let Some(bulb) = state.writes.iter_mut().find(|w| w.idx == idx) {
+            bulb.hue = hue
+        }
+    }
+
+    pub fn identify(&self, idx: u8) {
+        self.inner.lock().unwrap().identify.push(idx);
+    }
+
+    /// Brightness we're asking the bulb for, 0 while it's switched off
+    pub fn brightness(&self, idx: u8) -> f64 {
+        let state = self.inner.lock().unwrap();
+        let on = state.reads.iter().find(|r| r.idx == idx).is_none_or(|r| r.on);
+        let brightness = state.writes.iter().find(|w| w.idx == idx);
+        match brightness {
+            Some(w) if on => w.brightness.clamp(0.0, 1.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Luminous flux the bulb puts out at full brightness
+    pub fn lumens(&self, idx: u8) -> f32 {
+        let state = self.inner.lock().unwrap();
+        let read = state.reads.iter().find(|r| r.idx == idx);
+        read.and_then(|r| r.maxlumen).unwrap_or(DEFAULT_LUMENS) as f32
+    }
+
     pub fn ready(&self) -> bool {
         self.inner.lock().unwrap().ready
     }
@@ -175,6 +240,11 @@ fn run(state: Arc<Mutex<State>>, conn: Conn) -> Result<(), Error> {
     }
 
     loop {
+        let identify = std::mem::take(&mut state.lock().unwrap().identify);
+        for idx in identify {
+            conn.alert(idx)?
+        }
+
         let updates: Vec<(u8, BulbWrite)> = {
             let state = state.lock().unwrap();
             state
@@ -187,8 +257,8 @@ fn run(state: Arc<Mutex<State>>, conn: Conn) -> Result<(), Error> {
         };
 
         if last_sent.is_none() || last_sent.as_ref().unwrap() != &updates {
-            for (idx, update) in updates.clone() {
-                conn.set_bulb_state(idx, update.hue, update.brightness)?
+            for (idx, update) in &updates {
+                conn.set_bulb_state(*idx, update)?
             }
             last_sent = Some(updates);
         }
@@ -0,0 +1,128 @@
+// Saves and restores where things are in the room, so a demo can be set up once and replayed
+use crate::building::{assign_rooms, InRoom};
+use crate::bulb::{GhostMotion, Lamp, Trajectory};
+use crate::camera::SavedView;
+use crate::hover::egui_wants_keyboard;
+use crate::path::GhostPath;
+use crate::room::Room;
+use anyhow::Error;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const LAYOUT_FILE: &str = "layout.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LampPlacement {
+    pub index: u8,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    #[serde(default)]
+    pub room: Option<String>, // name of the room the lamp was placed in
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Default)]
+pub struct Layout {
+    pub lamps: Vec<LampPlacement>,
+    pub ghost_path: Option<GhostPath>,
+    #[serde(default)]
+    pub views: Vec<SavedView>,
+}
+
+impl Layout {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn lamp_transform(&self, index: u8) -> Option<Transform> {
+        self.lamps
+            .iter()
+            .find(|l| l.index == index)
+            .map(|l| Transform::from_translation(l.translation).with_rotation(l.rotation))
+    }
+
+    /// Name of the room the lamp was saved in
+    pub fn lamp_room(&self, index: u8) -> Option<&str> {
+        self.lamps
+            .iter()
+            .find(|l| l.index == index)
+            .and_then(|l| l.room.as_deref())
+    }
+}
+
+/// Puts loaded lamps back in the room they were saved in, which matters where rooms overlap
+fn restore_lamp_rooms(
+    mut commands: Commands,
+    layout: Res<Layout>,
+    lamps: Query<(Entity, &Lamp), Without<InRoom>>,
+    rooms: Query<(Entity, &Room)>,
+) {
+    for (entity, lamp) in lamps.iter() {
+        let Some(name) = layout.lamp_room(lamp.index) else {
+            continue;
+        };
+        if let Some((room, _)) = rooms.iter().find(|(_, room)| room.name == name) {
+            // after `assign_rooms`, so this wins over a guess from the position
+            commands.entity(entity).insert(InRoom(room));
+        }
+    }
+}
+
+/// Ctrl+S writes the current lamp positions, ghost path and saved views to disk
+fn save_layout(
+    keys: Res<Input<KeyCode>>,
+    mut layout: ResMut<Layout>,
+    lamps: Query<(&Lamp, &Transform, Option<&InRoom>)>,
+    rooms: Query<&Room>,
+    ghosts: Query<&GhostMotion>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !(ctrl && keys.just_pressed(KeyCode::S)) {
+        return;
+    }
+
+    layout.lamps = lamps
+        .iter()
+        .map(|(lamp, t, in_room)| LampPlacement {
+            index: lamp.index,
+            translation: t.translation,
+            rotation: t.rotation,
+            room: in_room
+                .and_then(|r| rooms.get(r.0).ok())
+                .map(|r| r.name.clone()),
+        })
+        .collect();
+    layout.ghost_path = ghosts.iter().find_map(|m| match &m.trajectory {
+        Trajectory::Path(path) => Some(path.clone()),
+        _ => None,
+    });
+
+    match layout.save(LAYOUT_FILE) {
+        Ok(()) => println!("layout saved to {LAYOUT_FILE}"),
+        Err(e) => eprintln!("failed to save layout: {e}"),
+    }
+}
+
+pub struct LayoutPlugin;
+
+impl Plugin for LayoutPlugin {
+    fn build(&self, app: &mut App) {
+        // loaded up front so startup systems can place things where they were saved
+        let layout = Layout::load(LAYOUT_FILE).unwrap_or_else(|e| {
+            println!("no layout loaded from {LAYOUT_FILE}: {e}");
+            Layout::default()
+        });
+        app.insert_resource(layout).add_systems(
+            Update,
+            (
+                save_layout.run_if(not(egui_wants_keyboard)),
+                restore_lamp_rooms.after(assign_rooms),
+            ),
+        );
+    }
+}
@@ -0,0 +1,445 @@
+// Physically grounded lighting check: lamps put out their rated lumens, and the floor and work
+// surfaces show the illuminance they get in lux, to see whether a layout is bright enough to read by.
+use crate::bulb::{dim_by_distance, Bulb};
+use crate::hover::egui_wants_keyboard;
+use crate::hue::{BulbState, DEFAULT_LUMENS};
+use crate::room::{Room, RoomBounds, RoomGeometry, RoomSnapshot};
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use futures_lite::future;
+use std::f32::consts::PI;
+
+// heatmap colors (srgb) at and above each illuminance
+const LUX_STOPS: [(f32, [f32; 3]); 6] = [
+    (0.0, [0.05, 0.05, 0.3]),
+    (50.0, [0.1, 0.3, 0.9]),
+    (150.0, [0.1, 0.8, 0.4]),
+    (300.0, [0.9, 0.9, 0.1]),
+    (500.0, [1.0, 0.5, 0.0]),
+    (1000.0, [1.0, 0.1, 0.1]),
+];
+const MAX_CELLS: usize = 200; // per side of a room
+const REFRESH: f32 = 0.25; // seconds between heatmap updates
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Illuminance {
+    pub enabled: bool, // H toggles; lamps use their rated lumens and the heatmap shows
+    pub heatmap: bool,
+    pub shadows: bool,
+    pub cell: f32,   // heatmap resolution in meters
+    pub target: f32, // lux, around 500 is comfortable for reading
+    pub opacity: f32,
+}
+
+impl Default for Illuminance {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            heatmap: true,
+            shadows: true,
+            cell: 0.25,
+            target: 500.0,
+            opacity: 0.7,
+        }
+    }
+}
+
+/// Illuminance at `point` on a surface facing `normal`, from isotropic lamps given as (position, lumens)
+fn lux_at(point: Vec3, normal: Vec3, lamps: &[(Vec3, f32)], meters_per_unit: f32) -> f32 {
+    lamps
+        .iter()
+        .map(|(position, lumens)| {
+            let to_lamp = (*position - point) * meters_per_unit;
+            let distance_squared = to_lamp.length_squared().max(1e-4);
+            let cosine = normal.dot(to_lamp / distance_squared.sqrt()).max(0.0);
+            // luminous intensity in candela, spread evenly over the sphere
+            lumens / (4.0 * PI) * cosine / distance_squared
+        })
+        .sum()
+}
+
+fn lux_color(lux: f32) -> Color {
+    let above = LUX_STOPS
+        .iter()
+        .position(|(stop, _)| *stop > lux)
+        .unwrap_or(LUX_STOPS.len());
+    let [r, g, b] = match above {
+        0 => LUX_STOPS[0].1,
+        i if i == LUX_STOPS.len() => LUX_STOPS[i - 1].1,
+        i => {
+            let ((low, a), (high, b)) = (LUX_STOPS[i - 1], LUX_STOPS[i]);
+            let t = (lux - low) / (high - low);
+            Vec3::from(a).lerp(Vec3::from(b), t).to_array()
+        }
+    };
+    Color::rgb(r, g, b)
+}
+
+#[derive(Component)]
+struct Heatmap;
+
+#[derive(Resource, Default)]
+struct HeatmapStats {
+    samples: usize,
+    at_target: usize,
+    mean: f32,
+    max: f32,
+}
+
+fn illuminance_keys(keys: Res<Input<KeyCode>>, mut settings: ResMut<Illuminance>) {
+    if keys.just_pressed(KeyCode::H) {
+        settings.enabled = !settings.enabled;
+    }
+}
+
+/// Rated flux of a bulb at its current brightness
+fn bulb_lumens(bulb_state: Option<&BulbState>, index: u8) -> f32 {
+    bulb_state.map_or(DEFAULT_LUMENS as f32, |state| {
+        state.lumens(index) * state.brightness(index) as f32
+    })
+}
+
+/// Length of a world unit in meters where `point` is, from the room it's in
+fn meters_per_unit(bounds: &RoomBounds, rooms: &Query<&Room>, point: Vec3) -> f32 {
+    bounds
+        .room_at(point)
+        .or(bounds.rooms.first().map(|(room, _, _)| *room))
+        .and_then(|room| rooms.get(room).ok())
+        .map_or(1.0, |room| room.meters_per_unit)
+}
+
+/// Point lights put out the bulbs' real flux, so the rendered room is as bright as the lux say
+fn photometric_lights(
+    settings: Res<Illuminance>,
+    bounds: Res<RoomBounds>,
+    rooms: Query<&Room>,
+    bulb_state: Option<Res<BulbState>>,
+    mut lights: Query<(&mut PointLight, &Bulb, &GlobalTransform)>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    for (mut light, bulb, transform) in lights.iter_mut() {
+        // Bevy takes distances in world units to be meters
+        let meters = meters_per_unit(&bounds, &rooms, transform.translation());
+        light.intensity = bulb_lumens(bulb_state.as_deref(), bulb.index) / (meters * meters);
+    }
+}
+
+/// Everything the heatmap is computed from, it's only recomputed when one of these changes
+#[derive(Clone, PartialEq)]
+struct HeatmapInputs {
+    lamps: Vec<(Vec3, f32)>,       // position, lumens
+    rooms: Vec<(Vec3, Vec3, f32)>, // min, max, meters per unit
+    geometry: RoomSnapshot,
+    shadows: bool,
+    cell: f32,
+    target: f32,
+    opacity: f32,
+}
+
+/// The heatmap being computed in the background, and what the one on screen was computed from
+#[derive(Resource, Default)]
+struct HeatmapJob {
+    since_update: f32,
+    built_from: Option<HeatmapInputs>,
+    task: Option<Task<(Mesh, HeatmapStats)>>,
+}
+
+/// Points on the highest upward facing surface under each grid cell, with their illuminance
+fn sample_room(
+    geometry: &RoomSnapshot,
+    (min, max): (Vec3, Vec3),
+    step: f32,
+    lux: impl Fn(Vec3, Vec3) -> f32,
+) -> Vec<Vec<Option<(Vec3, f32)>>> {
+    let cells = |extent: f32| ((extent / step).ceil() as usize + 1).min(MAX_CELLS);
+    let (nx, nz) = (cells(max.x - min.x), cells(max.z - min.z));
+    // start under the ceiling but above tables and counters
+    let start = min.y + 0.6 * (max.y - min.y);
+    (0..nx)
+        .map(|i| {
+            (0..nz)
+                .map(|j| {
+                    let origin = Vec3::new(min.x + i as f32 * step, start, min.z + j as f32 * step);
+                    let hit = geometry.hit(Ray {
+                        origin,
+                        direction: Vec3::NEG_Y,
+                    })?;
+                    // floors and tabletops, not the top edge of a wall
+                    (hit.normal.y > 0.7).then(|| (hit.point, lux(hit.point, hit.normal)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Heatmap mesh over every room, runs in a background task
+fn build_heatmap(inputs: &HeatmapInputs) -> (Mesh, HeatmapStats) {
+    let lux = |point: Vec3, normal: Vec3, meters_per_unit: f32| {
+        let visible: Vec<(Vec3, f32)> = inputs
+            .lamps
+            .iter()
+            .filter(|(position, _)| {
+                !inputs.shadows
+                    || inputs
+                        .geometry
+                        .line_of_sight(point + normal * 0.05, *position)
+            })
+            .copied()
+            .collect();
+        lux_at(point, normal, &visible, meters_per_unit)
+    };
+
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut colors: Vec<[f32; 4]> = vec![];
+    let mut indices: Vec<u32> = vec![];
+    let mut stats = HeatmapStats::default();
+    for (min, max, meters_per_unit) in &inputs.rooms {
+        let step = inputs.cell.max(0.01) / meters_per_unit.max(1e-3);
+        let grid = sample_room(&inputs.geometry, (*min, *max), step, |point, normal| {
+            lux(point, normal, *meters_per_unit)
+        });
+        let mut index = vec![vec![None; grid.first().map_or(0, |row| row.len())]; grid.len()];
+        for (i, row) in grid.iter().enumerate() {
+            for (j, sample) in row.iter().enumerate() {
+                let Some((point, lux)) = sample else {
+                    continue;
+                };
+                stats.samples += 1;
+                stats.at_target += (*lux >= inputs.target) as usize;
+                stats.mean += lux;
+                stats.max = stats.max.max(*lux);
+                index[i][j] = Some(positions.len() as u32);
+                // lifted a little so it doesn't flicker against the floor
+                positions.push((*point + Vec3::Y * 0.01).to_array());
+                let mut color = lux_color(*lux);
+                color.set_a(inputs.opacity);
+                colors.push(color.as_linear_rgba_f32());
+            }
+        }
+        for i in 0..grid.len().saturating_sub(1) {
+            for j in 0..index[i].len().saturating_sub(1) {
+                let corners = [(i, j), (i, j + 1), (i + 1, j), (i + 1, j + 1)];
+                let (Some(a), Some(b), Some(c), Some(d)) = corners.map(|(i, j)| index[i][j]).into()
+                else {
+                    continue;
+                };
+                // don't stretch cells from the floor up onto a table
+                let heights = [a, b, c, d].map(|v| positions[v as usize][1]);
+                let spread = heights.iter().fold(f32::NEG_INFINITY, |m, h| m.max(*h))
+                    - heights.iter().fold(f32::INFINITY, |m, h| m.min(*h));
+                if spread > step {
+                    continue;
+                }
+                indices.extend([a, b, c, c, b, d]);
+            }
+        }
+    }
+    if stats.samples > 0 {
+        stats.mean /= stats.samples as f32;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    (mesh, stats)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_heatmap(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Illuminance>,
+    mut job: ResMut<HeatmapJob>,
+    mut stats: ResMut<HeatmapStats>,
+    geometry: RoomGeometry,
+    bounds: Res<RoomBounds>,
+    rooms: Query<&Room>,
+    bulb_state: Option<Res<BulbState>>,
+    bulbs: Query<(&Bulb, &GlobalTransform, &ComputedVisibility)>,
+    overlay: Query<(Entity, &Handle<Mesh>), With<Heatmap>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !(settings.enabled && settings.heatmap) {
+        for (entity, _) in overlay.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        *job = HeatmapJob::default(); // dropping the task cancels it
+        return;
+    }
+
+    if let Some(task) = &mut job.task {
+        let Some((mesh, built)) = future::block_on(future::poll_once(task)) else {
+            return; // one at a time
+        };
+        job.task = None;
+        *stats = built;
+        if let Ok((_, handle)) = overlay.get_single() {
+            if let Some(existing) = meshes.get_mut(handle) {
+                *existing = mesh;
+            }
+        } else {
+            commands.spawn((
+                Name::new("lux heatmap"),
+                Heatmap,
+                NotShadowCaster,
+                PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(StandardMaterial {
+                        base_color: Color::WHITE,
+                        unlit: true,
+                        alpha_mode: AlphaMode::Blend,
+                        double_sided: true,
+                        cull_mode: None,
+                        ..default()
+                    }),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    job.since_update += time.delta_seconds();
+    if job.since_update < REFRESH && job.built_from.is_some() && !settings.is_changed() {
+        return;
+    }
+    let inputs = HeatmapInputs {
+        lamps: bulbs
+            .iter()
+            .filter(|(_, _, visibility)| visibility.is_visible_in_hierarchy())
+            .map(|(bulb, transform, _)| {
+                (
+                    transform.translation(),
+                    bulb_lumens(bulb_state.as_deref(), bulb.index),
+                )
+            })
+            .collect(),
+        rooms: bounds
+            .rooms
+            .iter()
+            .map(|(room, min, max)| {
+                let meters_per_unit = rooms.get(*room).map_or(1.0, |r| r.meters_per_unit);
+                (*min, *max, meters_per_unit)
+            })
+            .collect(),
+        geometry: geometry.snapshot(),
+        shadows: settings.shadows,
+        cell: settings.cell,
+        target: settings.target,
+        opacity: settings.opacity,
+    };
+    job.since_update = 0.0;
+    // nothing moved, dimmed or got edited
+    if job.built_from.as_ref() == Some(&inputs) {
+        return;
+    }
+    let built_from = inputs.clone();
+    job.task = Some(AsyncComputeTaskPool::get().spawn(async move { build_heatmap(&inputs) }));
+    job.built_from = Some(built_from);
+}
+
+fn legend(settings: Res<Illuminance>, stats: Res<HeatmapStats>, mut contexts: EguiContexts) {
+    if !(settings.enabled && settings.heatmap) {
+        return;
+    }
+    egui::Window::new("Illuminance").show(contexts.ctx_mut(), |ui| {
+        for (lux, [r, g, b]) in LUX_STOPS.iter().rev() {
+            ui.horizontal(|ui| {
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(24.0, 12.0), egui::Sense::hover());
+                let color = egui::Color32::from_rgb(
+                    (r * 255.0) as u8,
+                    (g * 255.0) as u8,
+                    (b * 255.0) as u8,
+                );
+                ui.painter().rect_filled(rect, 0.0, color);
+                ui.label(format!("{lux} lx"));
+            });
+        }
+        ui.separator();
+        if stats.samples > 0 {
+            let share = 100.0 * stats.at_target as f32 / stats.samples as f32;
+            ui.label(format!(
+                "{share:.0}% of surfaces at {} lx or more",
+                settings.target
+            ));
+            ui.label(format!(
+                "mean {:.0} lx, max {:.0} lx",
+                stats.mean, stats.max
+            ));
+        } else {
+            ui.label("no surfaces found, is the room loaded?");
+        }
+    });
+}
+
+pub struct IlluminancePlugin;
+
+impl Plugin for IlluminancePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Illuminance>()
+            .init_resource::<Illuminance>()
+            .init_resource::<HeatmapStats>()
+            .init_resource::<HeatmapJob>()
+            .add_systems(
+                Update,
+                (
+                    illuminance_keys.run_if(not(egui_wants_keyboard)),
+                    update_heatmap,
+                    legend,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, photometric_lights.after(dim_by_distance));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::RoomConfig;
+
+    #[test]
+    fn inverse_square_lux() {
+        // 800 lm spread over the sphere is about 63.7 cd, so 15.9 lux two meters below
+        let lamps = [(Vec3::new(0.0, 2.0, 0.0), 800.0)];
+        let below = lux_at(Vec3::ZERO, Vec3::Y, &lamps, 1.0);
+        assert!((below - 800.0 / (4.0 * PI) / 4.0).abs() < 1e-3);
+        // the same room modelled in centimeters
+        let cm = [(Vec3::new(0.0, 200.0, 0.0), 800.0)];
+        assert!((lux_at(Vec3::ZERO, Vec3::Y, &cm, 0.01) - below).abs() < 1e-3);
+        // and the bundled room, drawn five times larger than life
+        let bundled = RoomConfig::default().meters_per_unit();
+        let big = [(Vec3::new(0.0, 10.0, 0.0), 800.0)];
+        assert!((lux_at(Vec3::ZERO, Vec3::Y, &big, bundled) - below).abs() < 1e-3);
+        // lamps add up, and light from below doesn't count
+        let two = [lamps[0], lamps[0], (Vec3::new(0.0, -2.0, 0.0), 800.0)];
+        assert!((lux_at(Vec3::ZERO, Vec3::Y, &two, 1.0) - 2.0 * below).abs() < 1e-3);
+    }
+
+    #[test]
+    fn legend_colors() {
+        let rgb = |lux: f32| {
+            let c = lux_color(lux);
+            [c.r(), c.g(), c.b()]
+        };
+        assert_eq!(rgb(-10.0), LUX_STOPS[0].1);
+        assert_eq!(rgb(500.0), LUX_STOPS[4].1);
+        assert_eq!(rgb(5000.0), LUX_STOPS[5].1);
+        // halfway between the first two stops
+        let mid = rgb(25.0);
+        assert!((mid[2] - 0.6).abs() < 1e-5);
+    }
+}
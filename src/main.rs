@@ -6,10 +6,29 @@ use bevy_debug_grid::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use std::f32::consts::PI;
 
+mod ao;
+mod building;
 mod bulb;
+mod bvh;
+mod camera;
 mod colorize;
+mod cutaway;
+mod fade;
+mod falloff;
+mod floorplan;
+mod gizmo;
+mod highlight;
 mod hover;
 mod hue;
+mod layout;
+mod lux;
+mod occlusion;
+mod path;
+mod preview;
+mod room;
+mod scan;
+mod snap;
+mod touch;
 mod util;
 
 fn main() {
@@ -17,17 +36,29 @@ fn main() {
         .add_plugins(DefaultPlugins)
         //.add_plugins(bevy_flycam::prelude::PlayerPlugin)
         .add_systems(Startup, setup)
+        .add_plugins(layout::LayoutPlugin)
         .add_plugins(hover::MouseRayPlugin)
+        .add_plugins(camera::CameraPlugin)
+        .add_plugins(touch::TouchPlugin)
+        .add_plugins(highlight::HighlightPlugin)
+        .add_plugins(gizmo::GizmoPlugin)
+        .add_plugins(room::RoomPlugin)
+        .add_plugins(building::BuildingPlugin)
+        .add_plugins(fade::FadePlugin)
+        .add_plugins(cutaway::CutawayPlugin)
+        .add_plugins(snap::SnapPlugin)
+        .add_plugins(occlusion::OcclusionPlugin)
+        .add_plugins(path::PathPlugin)
         .add_plugins(bulb::BulbPlugin)
         .add_plugins(WorldInspectorPlugin::new())
         .add_plugins(DebugGridPlugin::with_floor_grid())
         .add_plugins(colorize::ColorizePlugin)
+        .add_plugins(lux::IlluminancePlugin)
         .run();
 }
 
 fn setup(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
     //mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
@@ -36,37 +67,23 @@ fn setup(
         color: Color::WHITE,
         brightness: 0.01,
     });
-    let scene = asset_server.load("room.gltf#Scene0");
-    commands.spawn(SceneBundle {
-        scene,
-        transform: Transform::from_scale(Vec3 {
-            x: 5.0,
-            y: 5.0,
-            z: 5.0,
-        })
-        .with_translation(Vec3 {
-            x: 0.0,
-            y: -1.0,
-            z: 0.0,
-        }),
-        ..default()
-    });
-
     // camera
+    let transform = Transform::from_xyz(-32.0, 24.0, 12.0).with_rotation(Quat::from_euler(
+        EulerRot::ZYX,
+        -0.0725,
+        -0.668,
+        -0.502,
+    ));
     commands
         .spawn((
+            camera::OrbitCamera::from_transform(&transform),
             Camera3dBundle {
                 camera: Camera {
                     hdr: true,
                     ..default()
                 },
                 tonemapping: Tonemapping::TonyMcMapface,
-                transform: Transform::from_xyz(-32.0, 24.0, 12.0).with_rotation(Quat::from_euler(
-                    EulerRot::ZYX,
-                    -0.0725,
-                    -0.668,
-                    -0.502,
-                )),
+                transform,
                 //projection: Projection::Orthographic(OrthographicProjection{scale: 0.08, ..OrthographicProjection::default()}),
                 ..Default::default()
             },
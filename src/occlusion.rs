@@ -0,0 +1,304 @@
+// Stops ghosts from lighting up bulbs on the other side of a wall
+use crate::bulb::Ghost;
+use crate::room::RoomGeometry;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Reflect, Clone, Copy, Default, PartialEq)]
+pub enum OcclusionMode {
+    /// Straight line distance, walls don't matter
+    #[default]
+    Off,
+    /// Ghosts without line of sight count for `factor` of their usual strength
+    Attenuate { factor: f32 },
+    /// Ghosts without line of sight are ignored
+    Exclude,
+    /// Distance walked around walls on a coarse grid, through doorways
+    PathDistance,
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Occlusion {
+    pub mode: OcclusionMode,
+    pub grid_cell: f32,   // size of a path distance grid cell, in world units
+    pub grid_height: f32, // height the grid is walked at, roughly where ghosts float
+}
+
+impl Default for Occlusion {
+    fn default() -> Self {
+        Self {
+            mode: OcclusionMode::Off,
+            grid_cell: 1.0,
+            grid_height: 1.0,
+        }
+    }
+}
+
+/// Walkable cells over the room, `east`/`south` say whether you can step to the next cell
+#[derive(Resource, Default)]
+pub(crate) struct RoomGrid {
+    origin: Vec3,
+    cell: f32,
+    width: usize,
+    depth: usize,
+    east: Vec<bool>,
+    south: Vec<bool>,
+    built_from: usize, // how many room meshes were loaded when this was built
+}
+
+impl RoomGrid {
+    /// Grid over `bounds`, stepping between neighbours that can see each other
+    fn build(
+        (min, max): (Vec3, Vec3),
+        cell: f32,
+        height: f32,
+        line_of_sight: impl Fn(Vec3, Vec3) -> bool,
+    ) -> Self {
+        let cell = cell.max(0.1);
+        let width = ((max.x - min.x) / cell).ceil().max(1.0) as usize;
+        let depth = ((max.z - min.z) / cell).ceil().max(1.0) as usize;
+        let mut grid = Self {
+            origin: Vec3::new(min.x, height, min.z),
+            cell,
+            width,
+            depth,
+            east: vec![false; width * depth],
+            south: vec![false; width * depth],
+            built_from: 0,
+        };
+        for z in 0..depth {
+            for x in 0..width {
+                let here = grid.center(x, z);
+                if x + 1 < width {
+                    grid.east[z * width + x] = line_of_sight(here, grid.center(x + 1, z));
+                }
+                if z + 1 < depth {
+                    grid.south[z * width + x] = line_of_sight(here, grid.center(x, z + 1));
+                }
+            }
+        }
+        grid
+    }
+
+    fn center(&self, x: usize, z: usize) -> Vec3 {
+        self.origin
+            + Vec3::new(
+                (x as f32 + 0.5) * self.cell,
+                0.0,
+                (z as f32 + 0.5) * self.cell,
+            )
+    }
+
+    fn cell_of(&self, pos: Vec3) -> usize {
+        let x = ((pos.x - self.origin.x) / self.cell).floor();
+        let z = ((pos.z - self.origin.z) / self.cell).floor();
+        let x = (x.max(0.0) as usize).min(self.width - 1);
+        let z = (z.max(0.0) as usize).min(self.depth - 1);
+        z * self.width + x
+    }
+
+    /// Walking distance from `from` to every cell, infinite where unreachable
+    fn distances(&self, from: Vec3) -> Vec<f32> {
+        let mut dist = vec![f32::INFINITY; self.width * self.depth];
+        let start = self.cell_of(from);
+        dist[start] = 0.0;
+        let mut queue = VecDeque::from([start]);
+        while let Some(i) = queue.pop_front() {
+            let (x, z) = (i % self.width, i / self.width);
+            let neighbours = [
+                (x + 1 < self.width && self.east[i]).then(|| i + 1),
+                (x > 0 && self.east[i - 1]).then(|| i - 1),
+                (z + 1 < self.depth && self.south[i]).then(|| i + self.width),
+                (z > 0 && self.south[i - self.width]).then(|| i - self.width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if dist[n].is_infinite() {
+                    dist[n] = dist[i] + self.cell;
+                    queue.push_back(n);
+                }
+            }
+        }
+        dist
+    }
+}
+
+/// Walking distance fields, one per ghost, refreshed every frame in `PathDistance` mode
+#[derive(Resource, Default)]
+pub(crate) struct GhostFields(HashMap<Entity, Vec<f32>>);
+
+pub(crate) fn update_fields(
+    settings: Res<Occlusion>,
+    geometry: RoomGeometry,
+    mut grid: ResMut<RoomGrid>,
+    mut fields: ResMut<GhostFields>,
+    ghosts: Query<(Entity, &GlobalTransform), With<Ghost>>,
+) {
+    fields.0.clear();
+    if settings.mode != OcclusionMode::PathDistance {
+        return;
+    }
+    let stale = grid.cell != settings.grid_cell.max(0.1)
+        || grid.origin.y != settings.grid_height
+        || grid.built_from != geometry.loaded();
+    if stale {
+        if let Some(bounds) = geometry.bounds() {
+            let (cell, height) = (settings.grid_cell, settings.grid_height);
+            *grid = RoomGrid::build(bounds, cell, height, |a, b| geometry.line_of_sight(a, b));
+            grid.built_from = geometry.loaded();
+        }
+    }
+    if grid.east.is_empty() {
+        return; // room not loaded yet
+    }
+    for (entity, transform) in ghosts.iter() {
+        fields
+            .0
+            .insert(entity, grid.distances(transform.translation()));
+    }
+}
+
+/// How a ghost is seen from a bulb once walls are taken into account
+pub struct Seen {
+    pub distance: f32,
+    pub factor: f32, // multiplies the strength after falloff
+}
+
+#[derive(SystemParam)]
+pub struct Occluder<'w, 's> {
+    settings: Res<'w, Occlusion>,
+    geometry: RoomGeometry<'w, 's>,
+    grid: Res<'w, RoomGrid>,
+    fields: Res<'w, GhostFields>,
+}
+
+impl<'w, 's> Occluder<'w, 's> {
+    /// `None` if the ghost should be left out entirely
+    pub fn seen(&self, ghost: Entity, ghost_pos: Vec3, bulb_pos: Vec3) -> Option<Seen> {
+        let visible = || self.geometry.line_of_sight(ghost_pos, bulb_pos);
+        let field = self.fields.0.get(&ghost);
+        let walk = field.map(|field| (&*self.grid, &field[..]));
+        seen(self.settings.mode, ghost_pos, bulb_pos, visible, walk)
+    }
+}
+
+/// How a ghost is seen from a bulb, `walk` is the grid and the ghost's walking distances over it
+fn seen(
+    mode: OcclusionMode,
+    ghost_pos: Vec3,
+    bulb_pos: Vec3,
+    visible: impl Fn() -> bool,
+    walk: Option<(&RoomGrid, &[f32])>,
+) -> Option<Seen> {
+    let distance = ghost_pos.distance(bulb_pos);
+    match mode {
+        OcclusionMode::Off => Some(Seen {
+            distance,
+            factor: 1.0,
+        }),
+        OcclusionMode::Attenuate { factor } => Some(Seen {
+            distance,
+            factor: if visible() {
+                1.0
+            } else {
+                factor.clamp(0.0, 1.0)
+            },
+        }),
+        OcclusionMode::Exclude => visible().then_some(Seen {
+            distance,
+            factor: 1.0,
+        }),
+        OcclusionMode::PathDistance => {
+            let Some((grid, field)) = walk.filter(|_| !visible()) else {
+                return Some(Seen {
+                    distance,
+                    factor: 1.0,
+                });
+            };
+            let cell = grid.cell_of(bulb_pos);
+            let walked = field[cell];
+            // walk to the cell, then the last stretch up to the bulb
+            let (x, z) = (cell % grid.width, cell / grid.width);
+            let last = grid.center(x, z).distance(bulb_pos);
+            walked.is_finite().then_some(Seen {
+                distance: (walked + last).max(distance),
+                factor: 1.0,
+            })
+        }
+    }
+}
+
+pub struct OcclusionPlugin;
+
+impl Plugin for OcclusionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Occlusion>()
+            .register_type::<OcclusionMode>()
+            .init_resource::<Occlusion>()
+            .init_resource::<RoomGrid>()
+            .init_resource::<GhostFields>()
+            .add_systems(Update, update_fields);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+
+    /// A 4 x 2 room split at x = 2 by a wall, open above `door` along z
+    fn room(door: f32) -> (RoomGrid, impl Fn(Vec3, Vec3) -> bool) {
+        let corners =
+            [(0.0, 0.0), (0.0, 2.0), (door, 0.0), (door, 2.0)].map(|(z, y)| Vec3::new(2.0, y, z));
+        let wall = Bvh::from_triangles(vec![
+            [corners[0], corners[1], corners[2]],
+            [corners[2], corners[1], corners[3]],
+        ]);
+        let line_of_sight = move |from: Vec3, to: Vec3| {
+            let distance = from.distance(to);
+            wall.intersect(from, (to - from) / distance)
+                .is_none_or(|hit| hit.t >= distance)
+        };
+        let bounds = (Vec3::ZERO, Vec3::new(4.0, 2.0, 2.0));
+        (
+            RoomGrid::build(bounds, 0.5, 1.0, &line_of_sight),
+            line_of_sight,
+        )
+    }
+
+    #[test]
+    fn path_goes_around_through_the_door() {
+        let (grid, line_of_sight) = room(1.5);
+        let (ghost, bulb) = (Vec3::new(1.25, 1.0, 0.25), Vec3::new(3.25, 1.0, 0.25));
+        assert!(!line_of_sight(ghost, bulb));
+        let field = grid.distances(ghost);
+        // up to the doorway row, across, and back down, in 0.5 steps
+        assert_eq!(field[grid.cell_of(bulb)], 5.0);
+        let (visible, walk) = (|| line_of_sight(ghost, bulb), Some((&grid, &field[..])));
+        let through = seen(OcclusionMode::PathDistance, ghost, bulb, visible, walk);
+        assert_eq!(through.unwrap().distance, 5.0);
+        // in plain sight it's the straight line
+        let near = Vec3::new(0.25, 1.0, 0.25);
+        let visible = || line_of_sight(ghost, near);
+        let straight = seen(OcclusionMode::PathDistance, ghost, near, visible, walk);
+        assert_eq!(straight.unwrap().distance, 1.0);
+    }
+
+    #[test]
+    fn solid_wall_blocks() {
+        let (grid, line_of_sight) = room(2.0);
+        let (ghost, bulb) = (Vec3::new(1.25, 1.0, 1.75), Vec3::new(3.25, 1.0, 1.75));
+        let visible = || line_of_sight(ghost, bulb);
+        assert!(seen(OcclusionMode::Exclude, ghost, bulb, visible, None).is_none());
+        let field = grid.distances(ghost);
+        assert!(field[grid.cell_of(bulb)].is_infinite());
+        let walk = Some((&grid, &field[..]));
+        assert!(seen(OcclusionMode::PathDistance, ghost, bulb, visible, walk).is_none());
+        // the same two spots see each other through a doorway
+        let (_, line_of_sight) = room(1.5);
+        let visible = || line_of_sight(ghost, bulb);
+        let through = seen(OcclusionMode::Exclude, ghost, bulb, visible, None);
+        assert_eq!(through.unwrap().distance, 2.0);
+    }
+}
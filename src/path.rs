@@ -0,0 +1,360 @@
+// Keyframed spline paths for ghosts, and an editor for drawing them on the floor
+use crate::bulb::{GhostMotion, Trajectory};
+use crate::hover::{egui_wants_keyboard, update_clicks, update_drag_start, MouseRay};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Reflect, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub position: Vec3,
+    pub time: f32, // seconds after the first keyframe
+}
+
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    CatmullRom,
+    /// Cubic Bezier with handles along the neighbour direction,
+    /// tension 0.5 matches Catmull-Rom, 0.0 gives straight lines
+    Bezier { tension: f32 },
+}
+
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Playback {
+    #[default]
+    Loop,
+    Once,
+}
+
+#[derive(Reflect, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GhostPath {
+    pub keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    pub playback: Playback,
+    pub closing_time: f32, // seconds from the last keyframe back to the first when looping
+}
+
+impl GhostPath {
+    pub fn duration(&self) -> f32 {
+        let Some(last) = self.keyframes.last() else {
+            return 0.0;
+        };
+        match self.playback {
+            Playback::Loop => last.time + self.closing_time,
+            Playback::Once => last.time,
+        }
+    }
+
+    fn point(&self, idx: isize) -> Vec3 {
+        let n = self.keyframes.len() as isize;
+        let idx = match self.playback {
+            Playback::Loop => idx.rem_euclid(n),
+            Playback::Once => idx.clamp(0, n - 1),
+        };
+        self.keyframes[idx as usize].position
+    }
+
+    pub fn sample(&self, t: f32) -> Option<Vec3> {
+        let n = self.keyframes.len();
+        match n {
+            0 => return None,
+            1 => return Some(self.keyframes[0].position),
+            _ => {}
+        }
+
+        let duration = self.duration();
+        let t = match self.playback {
+            Playback::Loop if duration > 0.0 => t.rem_euclid(duration),
+            _ => t.clamp(0.0, duration),
+        };
+
+        // find the segment [i, i+1] containing t, the closing segment wraps back to 0
+        let segments = match self.playback {
+            Playback::Loop => n,
+            Playback::Once => n - 1,
+        };
+        let mut seg = segments - 1;
+        for i in 0..segments {
+            let end = if i + 1 < n {
+                self.keyframes[i + 1].time
+            } else {
+                duration
+            };
+            if t <= end {
+                seg = i;
+                break;
+            }
+        }
+        let t0 = self.keyframes[seg].time;
+        let t1 = if seg + 1 < n {
+            self.keyframes[seg + 1].time
+        } else {
+            duration
+        };
+        let u = if t1 > t0 {
+            ((t - t0) / (t1 - t0)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let i = seg as isize;
+        let (p0, p1, p2, p3) = (
+            self.point(i - 1),
+            self.point(i),
+            self.point(i + 1),
+            self.point(i + 2),
+        );
+        Some(match self.interpolation {
+            Interpolation::CatmullRom => catmull_rom(p0, p1, p2, p3, u),
+            Interpolation::Bezier { tension } => {
+                let h1 = p1 + (p2 - p0) * (tension / 3.0);
+                let h2 = p2 - (p3 - p1) * (tension / 3.0);
+                cubic_bezier(p1, h1, h2, p2, u)
+            }
+        })
+    }
+
+    /// Time of the keyframe closest to `pos`, used to join the path without jumping
+    pub fn closest_time(&self, pos: Vec3) -> f32 {
+        self.keyframes
+            .iter()
+            .min_by(|a, b| {
+                a.position
+                    .distance_squared(pos)
+                    .total_cmp(&b.position.distance_squared(pos))
+            })
+            .map(|k| k.time)
+            .unwrap_or_default()
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, u: f32) -> Vec3 {
+    let (u2, u3) = (u * u, u * u * u);
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * u
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * u3)
+}
+
+fn cubic_bezier(b0: Vec3, b1: Vec3, b2: Vec3, b3: Vec3, u: f32) -> Vec3 {
+    let v = 1.0 - u;
+    b0 * (v * v * v) + b1 * (3.0 * v * v * u) + b2 * (3.0 * v * u * u) + b3 * (u * u * u)
+}
+
+/// Draws a path by clicking on the floor.
+/// P starts/stops recording, Backspace drops the last point,
+/// stopping hands the path to every ghost.
+#[derive(Resource, Reflect)]
+pub struct PathEditor {
+    pub floor_height: f32,
+    pub ghost_height: f32,
+    pub seconds_per_unit: f32, // walking pace used to time new keyframes
+    #[reflect(ignore)]
+    recording: Option<GhostPath>,
+}
+
+impl Default for PathEditor {
+    fn default() -> Self {
+        Self {
+            floor_height: 0.0,
+            ghost_height: 1.0,
+            seconds_per_unit: 0.5,
+            recording: None,
+        }
+    }
+}
+
+impl PathEditor {
+    /// While recording, clicks place points instead of picking things up
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+/// P starts and finishes recording, Backspace drops the last point
+fn path_keys(
+    mut editor: ResMut<PathEditor>,
+    keys: Res<Input<KeyCode>>,
+    mut ghosts: Query<(&mut GhostMotion, &Transform)>,
+) {
+    let editor = &mut *editor;
+    if keys.just_pressed(KeyCode::P) {
+        match editor.recording.take() {
+            None => {
+                println!("recording ghost path, click the floor to add points");
+                editor.recording = Some(GhostPath::default());
+            }
+            Some(mut path) if path.keyframes.len() > 1 => {
+                let (first, last) = (path.point(0), path.point(-1));
+                path.closing_time = first.distance(last) * editor.seconds_per_unit;
+                println!("recorded path with {} points", path.keyframes.len());
+                for (mut motion, transform) in ghosts.iter_mut() {
+                    let phase = path.closest_time(transform.translation);
+                    motion.trajectory = Trajectory::Path(path.clone());
+                    motion.set_phase(phase as f64);
+                }
+            }
+            Some(_) => println!("path needs at least two points, discarding"),
+        }
+    }
+    if let Some(path) = editor.recording.as_mut() {
+        if keys.just_pressed(KeyCode::Back) {
+            path.keyframes.pop();
+        }
+    }
+}
+
+pub(crate) fn edit_path(
+    mut editor: ResMut<PathEditor>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    ray_query: Query<&MouseRay>,
+) {
+    let editor = &mut *editor;
+    let Some(path) = editor.recording.as_mut() else {
+        return;
+    };
+    // the point has the press, so it neither selects nor drags what's under it
+    if mouse_button_input.clear_just_pressed(MouseButton::Left) {
+        for MouseRay { ray, .. } in ray_query.iter() {
+            let t = (editor.floor_height - ray.origin.y) / ray.direction.y;
+            if !t.is_finite() || t <= 0.0 {
+                continue;
+            }
+            let mut position = ray.origin + ray.direction * t;
+            position.y = editor.ghost_height;
+            let time = path.keyframes.last().map_or(0.0, |k| {
+                k.time + k.position.distance(position) * editor.seconds_per_unit
+            });
+            path.keyframes.push(Keyframe { position, time });
+        }
+    }
+}
+
+fn draw_paths(editor: Res<PathEditor>, ghosts: Query<&GhostMotion>, mut gizmos: Gizmos) {
+    let mut draw = |path: &GhostPath, color: Color| {
+        for k in &path.keyframes {
+            gizmos.sphere(k.position, Quat::IDENTITY, 0.2, color);
+        }
+        let steps = path.keyframes.len() * 16;
+        let duration = path.duration();
+        gizmos.linestrip(
+            (0..=steps).filter_map(|s| path.sample(duration * s as f32 / steps as f32)),
+            color,
+        );
+    };
+    if let Some(path) = &editor.recording {
+        draw(path, Color::ORANGE);
+    }
+    for motion in ghosts.iter() {
+        if let Trajectory::Path(path) = &motion.trajectory {
+            draw(path, Color::CYAN);
+        }
+    }
+}
+
+pub struct PathPlugin;
+
+impl Plugin for PathPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PathEditor>()
+            .register_type::<GhostPath>()
+            .register_type::<Keyframe>()
+            .register_type::<Vec<Keyframe>>()
+            .register_type::<Interpolation>()
+            .register_type::<Playback>()
+            .insert_resource(PathEditor::default())
+            .add_systems(Update, path_keys.run_if(not(egui_wants_keyboard)))
+            .add_systems(
+                Update,
+                edit_path
+                    .after(path_keys)
+                    .before(update_clicks)
+                    .before(update_drag_start),
+            )
+            .add_systems(Update, draw_paths);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(playback: Playback, interpolation: Interpolation) -> GhostPath {
+        GhostPath {
+            keyframes: vec![
+                Keyframe {
+                    position: Vec3::new(0.0, 0.0, 0.0),
+                    time: 0.0,
+                },
+                Keyframe {
+                    position: Vec3::new(1.0, 0.0, 0.0),
+                    time: 1.0,
+                },
+                Keyframe {
+                    position: Vec3::new(1.0, 0.0, 1.0),
+                    time: 3.0,
+                },
+                Keyframe {
+                    position: Vec3::new(0.0, 0.0, 1.0),
+                    time: 4.0,
+                },
+            ],
+            interpolation,
+            playback,
+            closing_time: 2.0,
+        }
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        for interpolation in [
+            Interpolation::CatmullRom,
+            Interpolation::Bezier { tension: 0.3 },
+        ] {
+            let path = square(Playback::Once, interpolation);
+            for k in &path.keyframes {
+                let p = path.sample(k.time).unwrap();
+                assert!(p.distance(k.position) < 1e-5, "{p:?} != {:?}", k.position);
+            }
+        }
+    }
+
+    #[test]
+    fn once_clamps_at_ends() {
+        let path = square(Playback::Once, Interpolation::CatmullRom);
+        assert_eq!(path.sample(-1.0), Some(Vec3::ZERO));
+        assert_eq!(path.sample(100.0), Some(Vec3::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let path = square(Playback::Loop, Interpolation::CatmullRom);
+        assert_eq!(path.duration(), 6.0);
+        let a = path.sample(1.5).unwrap();
+        let b = path.sample(7.5).unwrap();
+        assert!(a.distance(b) < 1e-5);
+        // closing segment ends back at the first keyframe
+        assert!(path.sample(5.999).unwrap().distance(Vec3::ZERO) < 1e-2);
+    }
+
+    #[test]
+    fn bezier_half_tension_matches_catmull_rom() {
+        let cr = square(Playback::Loop, Interpolation::CatmullRom);
+        let bz = square(Playback::Loop, Interpolation::Bezier { tension: 0.5 });
+        for i in 0..60 {
+            let t = i as f32 * 0.1;
+            assert!(cr.sample(t).unwrap().distance(bz.sample(t).unwrap()) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn degenerate_paths() {
+        let mut path = GhostPath::default();
+        assert_eq!(path.sample(1.0), None);
+        path.keyframes.push(Keyframe {
+            position: Vec3::ONE,
+            time: 0.0,
+        });
+        assert_eq!(path.sample(1.0), Some(Vec3::ONE));
+    }
+}
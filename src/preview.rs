@@ -0,0 +1,264 @@
+// Software light preview: how much light each room vertex gets from the bulbs, multiplied into
+// the colorized vertex colors. Readable without point-light shadows or a strong GPU. Only bulbs
+// that moved, changed color or dimmed are recomputed, a few meshes' worth per frame.
+use crate::bulb::Bulb;
+use crate::colorize::{vertex_normals, Colorized};
+use crate::cutaway::CutawayMesh;
+use crate::hue::BulbState;
+use crate::room::{RoomGeometry, RoomMesh};
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const WORK_PER_FRAME: usize = 200_000; // vertex and bulb pairs
+const SHADOW_BIAS: f32 = 0.01;
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct LightPreview {
+    pub enabled: bool, // L toggles
+    pub shadows: bool, // cast a ray to each bulb, slow on big rooms
+    pub power: f32,    // irradiance of a fully bright bulb one unit away
+    pub ambient: f32,  // added everywhere so unlit corners aren't black
+}
+
+impl Default for LightPreview {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shadows: true,
+            power: 10.0,
+            ambient: 0.05,
+        }
+    }
+}
+
+/// What a bulb contributes, compared to tell when it needs recomputing
+#[derive(Clone, Copy, PartialEq)]
+struct BulbLight {
+    position: Vec3,
+    color: Vec3, // linear rgb, already scaled by brightness and power
+}
+
+impl BulbLight {
+    fn differs(&self, other: &Self) -> bool {
+        self.position.distance(other.position) > 1e-3 || self.color.distance(other.color) > 1e-3
+    }
+}
+
+/// Lambert and inverse square falloff
+fn irradiance(position: Vec3, normal: Vec3, light: &BulbLight) -> Vec3 {
+    let to_light = light.position - position;
+    let distance_squared = to_light.length_squared().max(1e-4);
+    let cosine = normal.dot(to_light / distance_squared.sqrt()).max(0.0);
+    light.color * cosine / distance_squared
+}
+
+struct MeshLighting {
+    base: Arc<Vec<[f32; 4]>>, // the colors lighting was last multiplied onto
+    transform: Mat4,
+    positions: Vec<Vec3>, // world space
+    normals: Vec<Vec3>,
+    bulbs: HashMap<u8, (BulbLight, Vec<Vec3>)>,
+    total: Vec<Vec3>,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct Lighting(HashMap<Handle<Mesh>, MeshLighting>);
+
+fn lit_colors(base: &[[f32; 4]], total: &[Vec3], ambient: f32) -> Vec<[f32; 4]> {
+    base.iter()
+        .zip(total)
+        .map(|([r, g, b, a], light)| {
+            let light = *light + Vec3::splat(ambient);
+            [r * light.x, g * light.y, b * light.z, *a]
+        })
+        .collect()
+}
+
+type LitMesh<'a> = (
+    &'a Handle<Mesh>,
+    &'a GlobalTransform,
+    Option<&'a CutawayMesh>,
+);
+
+pub(crate) fn preview_keys(keys: Res<Input<KeyCode>>, mut preview: ResMut<LightPreview>) {
+    if keys.just_pressed(KeyCode::L) {
+        preview.enabled = !preview.enabled;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn light_preview(
+    preview: Res<LightPreview>,
+    colorized: Res<Colorized>,
+    bulb_state: Option<Res<BulbState>>,
+    geometry: RoomGeometry,
+    mut lighting: ResMut<Lighting>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    bulbs: Query<(&Bulb, &PointLight, &GlobalTransform, &ComputedVisibility)>,
+    room_meshes: Query<LitMesh, With<RoomMesh>>,
+) {
+    if !preview.enabled {
+        // back to the unlit colors
+        for (handle, lit) in lighting.0.drain() {
+            if let Some(mesh) = meshes.get_mut(&handle) {
+                mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, lit.base.to_vec());
+            }
+        }
+        return;
+    }
+    let lights: HashMap<u8, BulbLight> = bulbs
+        .iter()
+        .filter(|(_, _, _, visibility)| visibility.is_visible_in_hierarchy())
+        .map(|(bulb, light, transform, _)| {
+            let brightness = bulb_state
+                .as_ref()
+                .map_or(1.0, |state| state.brightness(bulb.index) as f32);
+            let [r, g, b, _] = light.color.as_linear_rgba_f32();
+            let light = BulbLight {
+                position: transform.translation(),
+                color: Vec3::new(r, g, b) * brightness * preview.power,
+            };
+            (bulb.index, light)
+        })
+        .collect();
+
+    let mut work = 0;
+    for (handle, transform, cutaway) in room_meshes.iter() {
+        // cut away rooms are lit at the source and cut again from there
+        let handle = cutaway.map_or(handle, |c| &c.full);
+        let Some(base) = colorized.base.get(handle) else {
+            continue;
+        };
+        if work >= WORK_PER_FRAME {
+            break;
+        }
+        let matrix = transform.compute_matrix();
+        if lighting
+            .0
+            .get(handle)
+            .is_none_or(|lit| lit.transform != matrix)
+        {
+            let Some(mesh) = meshes.get(handle) else {
+                continue;
+            };
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                continue;
+            };
+            let normal_matrix = Mat3::from(transform.affine().matrix3).inverse().transpose();
+            let lit = MeshLighting {
+                base: base.clone(),
+                transform: matrix,
+                positions: positions
+                    .iter()
+                    .map(|p| matrix.transform_point3(Vec3::from(*p)))
+                    .collect(),
+                normals: vertex_normals(mesh, positions)
+                    .iter()
+                    .map(|n| (normal_matrix * *n).normalize_or_zero())
+                    .collect(),
+                bulbs: HashMap::new(),
+                total: vec![Vec3::ZERO; positions.len()],
+            };
+            lighting.0.insert(handle.clone_weak(), lit);
+        }
+        let Some(lit) = lighting.0.get_mut(handle) else {
+            continue;
+        };
+        let mut changed = !Arc::ptr_eq(&lit.base, base);
+        lit.base = base.clone();
+
+        // bulbs that went away or got switched off
+        let gone: Vec<u8> = lit
+            .bulbs
+            .keys()
+            .filter(|index| !lights.contains_key(index))
+            .copied()
+            .collect();
+        for index in gone {
+            if let Some((_, old)) = lit.bulbs.remove(&index) {
+                lit.total.iter_mut().zip(&old).for_each(|(t, o)| *t -= *o);
+                changed = true;
+            }
+        }
+        for (index, light) in &lights {
+            if lit
+                .bulbs
+                .get(index)
+                .is_some_and(|(old, _)| !old.differs(light))
+            {
+                continue;
+            }
+            if work >= WORK_PER_FRAME {
+                break; // the rest wait for the next frame
+            }
+            work += lit.positions.len();
+            let contribution: Vec<Vec3> = lit
+                .positions
+                .iter()
+                .zip(&lit.normals)
+                .map(|(p, n)| {
+                    let origin = *p + *n * SHADOW_BIAS;
+                    if preview.shadows && !geometry.line_of_sight(origin, light.position) {
+                        return Vec3::ZERO;
+                    }
+                    irradiance(*p, *n, light)
+                })
+                .collect();
+            let old = lit.bulbs.insert(*index, (*light, contribution));
+            let new = &lit.bulbs[index].1;
+            for (i, t) in lit.total.iter_mut().enumerate() {
+                *t += new[i] - old.as_ref().map_or(Vec3::ZERO, |(_, old)| old[i]);
+            }
+            changed = true;
+        }
+        if !changed && !preview.is_changed() {
+            continue;
+        }
+        if lit.base.len() != lit.total.len() {
+            continue;
+        }
+        let colors = lit_colors(&lit.base, &lit.total, preview.ambient);
+        if let Some(mesh) = meshes.get_mut(handle) {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lambert_inverse_square() {
+        let light = BulbLight {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            color: Vec3::ONE,
+        };
+        // straight below: 1 / 2²
+        let below = irradiance(Vec3::ZERO, Vec3::Y, &light);
+        assert!((below.x - 0.25).abs() < 1e-6);
+        // twice as far, a quarter of the light
+        let far = irradiance(Vec3::new(0.0, -2.0, 0.0), Vec3::Y, &light);
+        assert!((far.x - below.x / 4.0).abs() < 1e-6);
+        // at 60 degrees, half
+        let tilted = Quat::from_rotation_z(std::f32::consts::FRAC_PI_3) * Vec3::Y;
+        let side = irradiance(Vec3::ZERO, tilted, &light);
+        assert!((side.x - below.x / 2.0).abs() < 1e-6);
+        // facing away, nothing
+        assert_eq!(irradiance(Vec3::ZERO, Vec3::NEG_Y, &light), Vec3::ZERO);
+    }
+
+    #[test]
+    fn lighting_multiplies_base() {
+        let base = [[0.5, 0.5, 0.5, 1.0]];
+        let colors = lit_colors(&base, &[Vec3::new(1.0, 0.0, 0.0)], 0.1);
+        assert!((colors[0][0] - 0.55).abs() < 1e-6);
+        assert!((colors[0][1] - 0.05).abs() < 1e-6);
+        assert_eq!(colors[0][3], 1.0);
+    }
+}
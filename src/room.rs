@@ -0,0 +1,856 @@
+// The room model: which entities are walls and furniture, and ray queries against them
+use crate::building::Building;
+use crate::bvh::{geometry_hash, Bvh, MeshBvhs, StableHasher};
+use crate::colorize::Colorize;
+use crate::cutaway::{CutawayMesh, CutawayShell};
+use crate::floorplan::FloorPlan;
+use crate::hover::egui_wants_keyboard;
+use crate::scan::{Scan, ScanOptions};
+use anyhow::{anyhow, Error};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use futures_lite::future;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+use std::sync::Arc;
+
+// the example room shipped in assets/, the only model with a scale and offset preset
+const BUNDLED_ROOM: &str = "room.gltf";
+
+/// Where the asset server loads from, models, floor plans and buildings are named relative to it
+pub fn asset_dir() -> std::path::PathBuf {
+    bevy::asset::FileAssetIo::get_base_path().join("assets")
+}
+
+/// Which model to load as the room and how to fit it, every site has a different apartment
+#[derive(Reflect, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RoomConfig {
+    pub path: String, // glTF, GLB, OBJ, PLY or a floor plan, relative to the assets folder or absolute
+    pub scale: f32,   // model units to meters
+    pub floor_offset: f32, // moves the model up or down so its floor sits on the grid
+    #[serde(default)]
+    pub scan: ScanOptions, // only for OBJ and PLY
+    #[serde(default)]
+    pub name: String, // defaults to the file name
+    #[serde(default)]
+    pub level: i32, // floor of the building, 0 is the ground floor
+    #[serde(default)]
+    pub position: Vec3, // where the room sits in the building
+}
+
+/// The bundled room, fitted to the grid
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            path: BUNDLED_ROOM.into(),
+            scale: 5.0,
+            floor_offset: -1.0,
+            scan: ScanOptions::default(),
+            name: String::new(),
+            level: 0,
+            position: Vec3::ZERO,
+        }
+    }
+}
+
+impl RoomConfig {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    /// Switch to another model. Its scale and floor offset go back to the model's own: the
+    /// bundled room's preset, or as-is for everything else (scans, floor plans, your own models).
+    pub fn fit_to(&mut self, path: String) {
+        let fitted = if path == BUNDLED_ROOM {
+            Self::default()
+        } else {
+            Self {
+                scale: 1.0,
+                floor_offset: 0.0,
+                ..default()
+            }
+        };
+        self.path = path;
+        self.scale = fitted.scale;
+        self.floor_offset = fitted.floor_offset;
+    }
+
+    /// `--room-config site.json`, or `--room model.glb [--scale 1.0] [--floor-offset 0.0]`,
+    /// scans also take `[--max-triangles 200000] [--align-floor]`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, Error> {
+        let mut args = args.into_iter();
+        let mut config: Option<Self> = None;
+        // given on the command line, win over the model's own whatever the order
+        let (mut scale, mut floor_offset) = (None, None);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{arg} needs a value"));
+            match arg.as_str() {
+                "--room-config" => config = Some(Self::load(&value()?)?),
+                "--room" => config.get_or_insert_with(Self::default).fit_to(value()?),
+                "--scale" => {
+                    config.get_or_insert_with(Self::default);
+                    scale = Some(value()?.parse()?);
+                }
+                "--floor-offset" => {
+                    config.get_or_insert_with(Self::default);
+                    floor_offset = Some(value()?.parse()?);
+                }
+                "--max-triangles" => {
+                    config.get_or_insert_with(Self::default).scan.max_triangles =
+                        Some(value()?.parse()?)
+                }
+                "--align-floor" => config.get_or_insert_with(Self::default).scan.align_floor = true,
+                _ => {}
+            }
+        }
+        if let Some(config) = &mut config {
+            config.scale = scale.unwrap_or(config.scale);
+            config.floor_offset = floor_offset.unwrap_or(config.floor_offset);
+        }
+        Ok(config)
+    }
+
+    fn is_scan(&self) -> bool {
+        self.path.ends_with(".obj") || self.path.ends_with(".ply")
+    }
+
+    fn is_floor_plan(&self) -> bool {
+        self.path.ends_with(".json") || self.path.ends_with(".svg")
+    }
+
+    pub fn name(&self) -> &str {
+        if self.name.is_empty() {
+            &self.path
+        } else {
+            &self.name
+        }
+    }
+
+    /// Length of one world unit in meters. glTF is in meters, and so are scans and floor plans
+    /// once loaded, so it's whatever `scale` stretched a meter to.
+    pub fn meters_per_unit(&self) -> f32 {
+        1.0 / self.scale.max(f32::EPSILON)
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_scale(Vec3::splat(self.scale))
+            .with_translation(self.position + Vec3::new(0.0, self.floor_offset, 0.0))
+    }
+}
+
+/// (Re)spawns every room whenever the building changes
+#[allow(clippy::too_many_arguments)]
+fn spawn_rooms(
+    mut commands: Commands,
+    building: Res<Building>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    rooms: Query<Entity, With<Room>>,
+    mut bounds: ResMut<RoomBounds>,
+    mut scans: ResMut<LoadingScans>,
+) {
+    if !building.is_changed() {
+        return;
+    }
+    for room in rooms.iter() {
+        commands.entity(room).despawn_recursive();
+    }
+    *bounds = RoomBounds::default();
+    scans.0.clear(); // dropping the tasks cancels them
+    for config in &building.rooms {
+        spawn_room(
+            &mut commands,
+            config,
+            &asset_server,
+            &mut meshes,
+            &mut materials,
+            &mut scans,
+        );
+    }
+}
+
+fn spawn_room(
+    commands: &mut Commands,
+    config: &RoomConfig,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    scans: &mut LoadingScans,
+) {
+    println!("loading room {} at scale {}", config.path, config.scale);
+    let room = Room {
+        name: config.name().to_string(),
+        level: config.level,
+        meters_per_unit: config.meters_per_unit(),
+    };
+    // floor plans and scans are read straight from disk, where the asset server would look for them
+    let path = asset_dir()
+        .join(&config.path)
+        .to_string_lossy()
+        .into_owned();
+    if config.is_scan() {
+        // scans can have millions of triangles, read and index them without stalling the app
+        let options = config.scan.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let mesh = Scan::load(&path, &options)?.into_mesh();
+            let bvh = Bvh::from_mesh(&mesh).ok_or_else(|| anyhow!("scan has no vertices"))?;
+            let hash = geometry_hash(&mesh);
+            Ok((mesh, hash, bvh))
+        });
+        scans.0.push(LoadingScan {
+            room,
+            path: config.path.clone(),
+            transform: config.transform(),
+            task,
+        });
+        return;
+    }
+    if config.is_floor_plan() {
+        let parts = match FloorPlan::load(&path).and_then(|plan| plan.meshes()) {
+            Ok(parts) => parts,
+            Err(e) => {
+                eprintln!("failed to load room {}: {e}", config.path);
+                return;
+            }
+        };
+        let parts = parts
+            .into_iter()
+            .map(|(name, mesh)| (name, meshes.add(mesh)))
+            .collect();
+        spawn_parts(commands, room, config.transform(), parts, materials);
+        return;
+    }
+    commands.spawn((
+        Name::new(room.name.clone()),
+        room,
+        Colorize::default(),
+        SceneBundle {
+            scene: asset_server.load(format!("{}#Scene0", config.path)),
+            transform: config.transform(),
+            ..default()
+        },
+    ));
+}
+
+/// A room built from meshes we made ourselves rather than a glTF scene
+fn spawn_parts(
+    commands: &mut Commands,
+    room: Room,
+    transform: Transform,
+    parts: Vec<(&'static str, Handle<Mesh>)>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        ..default()
+    });
+    commands
+        .spawn((
+            Name::new(room.name.clone()),
+            room,
+            Colorize::default(),
+            SpatialBundle::from_transform(transform),
+        ))
+        .with_children(|room| {
+            for (name, mesh) in parts {
+                room.spawn((
+                    Name::new(name),
+                    PbrBundle {
+                        mesh,
+                        material: material.clone(),
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+/// A scan being read in the background, with the room it becomes
+struct LoadingScan {
+    room: Room,
+    path: String,
+    transform: Transform,
+    task: Task<Result<(Mesh, u64, Bvh), Error>>, // mesh, its geometry hash and BVH
+}
+
+#[derive(Resource, Default)]
+struct LoadingScans(Vec<LoadingScan>);
+
+fn finish_scans(
+    mut commands: Commands,
+    mut scans: ResMut<LoadingScans>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut bvhs: ResMut<MeshBvhs>,
+) {
+    for mut scan in std::mem::take(&mut scans.0) {
+        let Some(loaded) = future::block_on(future::poll_once(&mut scan.task)) else {
+            scans.0.push(scan); // still reading
+            continue;
+        };
+        let (mesh, hash, bvh) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("failed to load room {}: {e}", scan.path);
+                continue;
+            }
+        };
+        let handle = meshes.add(mesh);
+        // already built, so the mesh being added doesn't build it again
+        bvhs.insert(&handle, hash, bvh);
+        spawn_parts(
+            &mut commands,
+            scan.room,
+            scan.transform,
+            vec![("scan", handle)],
+            &mut materials,
+        );
+    }
+}
+
+/// Ctrl+O picks another room model
+#[derive(Resource, Default)]
+struct RoomDialog {
+    open: bool,
+    draft: Option<RoomConfig>,
+    models: Vec<String>, // found in the assets folder when the dialog opens
+}
+
+fn find_models(dir: &std::path::Path, prefix: &str, found: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            find_models(&path, &format!("{name}/"), found);
+        } else if [".gltf", ".glb", ".obj", ".ply", ".json", ".svg"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+        {
+            found.push(name);
+        }
+    }
+}
+
+/// Ctrl+O opens the room dialog
+fn room_dialog_keys(
+    keys: Res<Input<KeyCode>>,
+    mut dialog: ResMut<RoomDialog>,
+    building: Res<Building>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl && keys.just_pressed(KeyCode::O) {
+        dialog.open = !dialog.open;
+        dialog.draft = Some(building.rooms.first().cloned().unwrap_or_default());
+        dialog.models.clear();
+        find_models(&asset_dir(), "", &mut dialog.models);
+        dialog.models.sort();
+    }
+}
+
+fn room_dialog(
+    mut dialog: ResMut<RoomDialog>,
+    mut building: ResMut<Building>,
+    mut contexts: EguiContexts,
+) {
+    if !dialog.open {
+        return;
+    }
+    let dialog = &mut *dialog;
+    let Some(draft) = &mut dialog.draft else {
+        return;
+    };
+    let mut open = true;
+    let (mut load, mut add) = (false, false);
+    egui::Window::new("Open room")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            for model in &dialog.models {
+                if ui.selectable_label(draft.path == *model, model).clicked() {
+                    draft.fit_to(model.clone());
+                }
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("path");
+                ui.text_edit_singleline(&mut draft.path);
+            });
+            ui.horizontal(|ui| {
+                ui.label("scale");
+                ui.add(egui::DragValue::new(&mut draft.scale).speed(0.1));
+                ui.label("floor offset");
+                ui.add(egui::DragValue::new(&mut draft.floor_offset).speed(0.1));
+            });
+            if draft.is_scan() {
+                ui.horizontal(|ui| {
+                    let mut decimate = draft.scan.max_triangles.is_some();
+                    ui.checkbox(&mut decimate, "decimate to");
+                    let mut max = draft.scan.max_triangles.unwrap_or(200_000);
+                    ui.add(egui::DragValue::new(&mut max).speed(1000));
+                    draft.scan.max_triangles = decimate.then_some(max);
+                    ui.checkbox(&mut draft.scan.align_floor, "align floor");
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label("name");
+                ui.text_edit_singleline(&mut draft.name);
+                ui.label("level");
+                ui.add(egui::DragValue::new(&mut draft.level));
+            });
+            ui.horizontal(|ui| {
+                ui.label("position");
+                ui.add(egui::DragValue::new(&mut draft.position.x).speed(0.1));
+                ui.add(egui::DragValue::new(&mut draft.position.y).speed(0.1));
+                ui.add(egui::DragValue::new(&mut draft.position.z).speed(0.1));
+            });
+            ui.horizontal(|ui| {
+                load = ui.button("Load").clicked();
+                add = ui.button("Add to building").clicked();
+            });
+        });
+    if load {
+        building.rooms = vec![draft.clone()];
+        open = false;
+    } else if add {
+        building.rooms.push(draft.clone());
+        open = false;
+    }
+    dialog.open = open;
+}
+
+/// Root of a loaded room scene
+#[derive(Component)]
+pub struct Room {
+    pub name: String,
+    pub level: i32,
+    pub meters_per_unit: f32, // for physical light levels
+}
+
+/// A mesh that is part of a room (walls, floor, furniture), tagged once the scene spawns
+#[derive(Component)]
+pub struct RoomMesh {
+    pub room: Entity,
+}
+
+/// Meshes under a room that aren't tagged yet; cutaway shells are drawn, not part of the room
+type Untagged = (With<Handle<Mesh>>, Without<RoomMesh>, Without<CutawayShell>);
+
+fn tag_room_meshes(
+    mut commands: Commands,
+    rooms: Query<Entity, With<Room>>,
+    children: Query<&Children>,
+    untagged: Query<(), Untagged>,
+) {
+    for room in rooms.iter() {
+        for entity in children.iter_descendants(room) {
+            if untagged.contains(entity) {
+                commands.entity(entity).insert(RoomMesh { room });
+            }
+        }
+    }
+}
+
+/// Ray casts against everything tagged [`RoomMesh`]
+#[derive(SystemParam)]
+pub struct RoomGeometry<'w, 's> {
+    meshes: Res<'w, Assets<Mesh>>,
+    bvhs: Res<'w, MeshBvhs>,
+    query: Query<'w, 's, RoomMeshData>,
+}
+
+type RoomMeshData = (
+    &'static Handle<Mesh>,
+    &'static GlobalTransform,
+    &'static RoomMesh,
+    &'static ComputedVisibility,
+    Option<&'static CutawayMesh>,
+);
+
+/// The uncut mesh, when the cutaway view has swapped in a cut copy
+fn full_mesh<'a>(handle: &'a Handle<Mesh>, cutaway: Option<&'a CutawayMesh>) -> &'a Handle<Mesh> {
+    cutaway.map_or(handle, |c| &c.full)
+}
+
+/// Where a ray meets the room
+pub struct RoomHit {
+    pub point: Vec3,
+    pub normal: Vec3, // world space, facing back along the ray
+}
+
+/// Closest distance along `ray` over meshes placed in the world
+fn closest<'a>(
+    meshes: impl Iterator<Item = (&'a Bvh, &'a GlobalTransform)>,
+    ray: Ray,
+) -> Option<f32> {
+    meshes
+        .filter_map(|(bvh, transform)| Some(bvh.intersect_world(ray, transform)?.t))
+        .min_by(f32::total_cmp)
+}
+
+/// Closest surface along `ray` over meshes placed in the world, with its normal
+fn closest_hit<'a>(
+    meshes: impl Iterator<Item = (&'a Bvh, &'a GlobalTransform)>,
+    ray: Ray,
+) -> Option<RoomHit> {
+    let (transform, hit) = meshes
+        .filter_map(|(bvh, transform)| Some((transform, bvh.intersect_world(ray, transform)?)))
+        .min_by(|(_, a), (_, b)| a.t.total_cmp(&b.t))?;
+    let [v0, v1, v2] = hit.triangle.map(|v| transform.transform_point(v));
+    let normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+    Some(RoomHit {
+        point: ray.get_point(hit.t),
+        normal: if normal.dot(ray.direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        },
+    })
+}
+
+/// Whether nothing stands between two points, given the distance to the first surface along a ray
+fn line_of_sight(from: Vec3, to: Vec3, cast: impl Fn(Ray) -> Option<f32>) -> bool {
+    let distance = from.distance(to);
+    if distance <= f32::EPSILON {
+        return true;
+    }
+    let ray = Ray {
+        origin: from,
+        direction: (to - from) / distance,
+    };
+    cast(ray).is_none_or(|t| t >= distance)
+}
+
+impl<'w, 's> RoomGeometry<'w, 's> {
+    /// Distance to the closest room surface along `ray`
+    /// Hidden rooms and cut away walls still count, they're there whether they're drawn or not
+    pub fn cast(&self, ray: Ray) -> Option<f32> {
+        let meshes = self
+            .query
+            .iter()
+            .filter_map(|(handle, transform, _, _, cutaway)| {
+                Some((&**self.bvhs.get(full_mesh(handle, cutaway))?, transform))
+            });
+        closest(meshes, ray)
+    }
+
+    /// Closest visible room surface along `ray`, with its normal
+    pub fn hit(&self, ray: Ray) -> Option<RoomHit> {
+        let meshes = self
+            .query
+            .iter()
+            .filter(|(_, _, _, visibility, _)| visibility.is_visible_in_hierarchy())
+            .filter_map(|(handle, transform, _, _, _)| {
+                Some((&**self.bvhs.get(handle)?, transform))
+            });
+        closest_hit(meshes, ray)
+    }
+
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        line_of_sight(from, to, |ray| self.cast(ray))
+    }
+
+    /// The room as it stands now, for ray casts in a background task
+    pub fn snapshot(&self) -> RoomSnapshot {
+        let mut snapshot = RoomSnapshot::default();
+        for (handle, transform, _, visibility, cutaway) in self.query.iter() {
+            if let Some(bvh) = self.bvhs.get(full_mesh(handle, cutaway)) {
+                snapshot.full.push((bvh.clone(), *transform));
+            }
+            if let Some(bvh) = self.bvhs.get(handle) {
+                if visibility.is_visible_in_hierarchy() {
+                    snapshot.visible.push((bvh.clone(), *transform));
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// World space bounding box of each room's loaded meshes
+    pub fn room_bounds(&self) -> Vec<(Entity, Vec3, Vec3)> {
+        use bevy::render::mesh::VertexAttributeValues;
+        let mut rooms: Vec<(Entity, Vec3, Vec3)> = vec![];
+        for (handle, transform, mesh, _, cutaway) in self.query.iter() {
+            let Some(VertexAttributeValues::Float32x3(positions)) = self
+                .meshes
+                .get(full_mesh(handle, cutaway))
+                .and_then(|m| m.attribute(Mesh::ATTRIBUTE_POSITION))
+            else {
+                continue;
+            };
+            let mat = transform.compute_matrix();
+            let index = match rooms.iter().position(|r| r.0 == mesh.room) {
+                Some(i) => i,
+                None => {
+                    rooms.push((mesh.room, Vec3::INFINITY, Vec3::NEG_INFINITY));
+                    rooms.len() - 1
+                }
+            };
+            let (_, min, max) = &mut rooms[index];
+            for p in positions {
+                let p = mat.transform_point3(Vec3::from(*p));
+                (*min, *max) = (min.min(p), max.max(p));
+            }
+        }
+        rooms
+    }
+
+    /// World space bounding box of all loaded room meshes
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.room_bounds()
+            .into_iter()
+            .map(|(_, min, max)| (min, max))
+            .reduce(|(a0, a1), (b0, b1)| (a0.min(b0), a1.max(b1)))
+    }
+
+    /// How many room meshes have finished loading, changes when the room does
+    pub fn loaded(&self) -> usize {
+        self.query
+            .iter()
+            .filter(|(handle, _, _, _, cutaway)| self.meshes.contains(full_mesh(handle, *cutaway)))
+            .count()
+    }
+
+    /// Every loaded room mesh has its BVH, so rays see the whole room
+    pub fn settled(&self) -> bool {
+        self.query.iter().all(|(handle, _, _, _, cutaway)| {
+            let full = full_mesh(handle, cutaway);
+            !self.meshes.contains(full) || self.bvhs.get(full).is_some()
+        })
+    }
+
+    /// Stable hash of every room mesh's shape and placement, whatever order they're in
+    pub fn fingerprint(&self) -> u64 {
+        let mut meshes: Vec<u64> = self
+            .query
+            .iter()
+            .filter_map(|(handle, transform, _, _, cutaway)| {
+                let mut hasher = StableHasher::default();
+                hasher.write_u64(self.bvhs.geometry(full_mesh(handle, cutaway))?);
+                for v in transform.compute_matrix().to_cols_array() {
+                    hasher.write_u32(v.to_bits());
+                }
+                Some(hasher.finish())
+            })
+            .collect();
+        meshes.sort_unstable();
+        let mut hasher = StableHasher::default();
+        meshes.iter().for_each(|m| hasher.write_u64(*m));
+        hasher.finish()
+    }
+}
+
+/// Room meshes frozen at one moment, answers the same ray queries as [`RoomGeometry`] off the main thread
+#[derive(Clone, Default)]
+pub struct RoomSnapshot {
+    visible: Vec<(Arc<Bvh>, GlobalTransform)>, // what `hit` sees
+    full: Vec<(Arc<Bvh>, GlobalTransform)>,    // uncut and hidden meshes too, what `cast` sees
+}
+
+impl RoomSnapshot {
+    pub fn cast(&self, ray: Ray) -> Option<f32> {
+        closest(self.full.iter().map(|(bvh, t)| (&**bvh, t)), ray)
+    }
+
+    pub fn hit(&self, ray: Ray) -> Option<RoomHit> {
+        closest_hit(self.visible.iter().map(|(bvh, t)| (&**bvh, t)), ray)
+    }
+
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        line_of_sight(from, to, |ray| self.cast(ray))
+    }
+}
+
+// same BVHs in the same places, without comparing any triangles
+impl PartialEq for RoomSnapshot {
+    fn eq(&self, other: &Self) -> bool {
+        let same = |a: &[(Arc<Bvh>, GlobalTransform)], b: &[(Arc<Bvh>, GlobalTransform)]| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((a, at), (b, bt))| Arc::ptr_eq(a, b) && at == bt)
+        };
+        same(&self.visible, &other.visible) && same(&self.full, &other.full)
+    }
+}
+
+/// World space box around the loaded room, dragged lamps and moving ghosts are kept inside it
+#[derive(Resource, Default)]
+pub struct RoomBounds {
+    pub bounds: Option<(Vec3, Vec3)>, // min, max; `None` until the room has loaded
+    pub rooms: Vec<(Entity, Vec3, Vec3)>, // each room on its own
+    built_from: usize,                // how many room meshes were loaded when this was computed
+}
+
+impl RoomBounds {
+    /// Whether `room`'s box holds `point`
+    pub fn contains(&self, room: Entity, point: Vec3) -> bool {
+        self.rooms
+            .iter()
+            .any(|(r, min, max)| *r == room && point.cmpge(*min).all() && point.cmple(*max).all())
+    }
+
+    /// Smallest room whose box holds `point`
+    pub fn room_at(&self, point: Vec3) -> Option<Entity> {
+        self.rooms
+            .iter()
+            .filter(|(_, min, max)| point.cmpge(*min).all() && point.cmple(*max).all())
+            .min_by(|a, b| {
+                let volume = |(_, min, max): &&(Entity, Vec3, Vec3)| {
+                    (*max - *min)
+                        .max(Vec3::ZERO)
+                        .to_array()
+                        .iter()
+                        .product::<f32>()
+                };
+                volume(a).total_cmp(&volume(b))
+            })
+            .map(|(room, _, _)| *room)
+    }
+
+    pub fn clamp(&self, point: Vec3) -> Vec3 {
+        match self.bounds {
+            Some((min, max)) => point.clamp(min, max),
+            None => point,
+        }
+    }
+}
+
+fn update_room_bounds(geometry: RoomGeometry, mut bounds: ResMut<RoomBounds>) {
+    let loaded = geometry.loaded();
+    if loaded == bounds.built_from {
+        return;
+    }
+    bounds.rooms = geometry.room_bounds();
+    bounds.bounds = geometry.bounds();
+    bounds.built_from = loaded;
+    if let Some((min, max)) = bounds.bounds {
+        println!("room bounds: {min} to {max}");
+    }
+}
+
+pub struct RoomPlugin;
+
+impl Plugin for RoomPlugin {
+    fn build(&self, app: &mut App) {
+        let building = Building::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("bad room arguments: {e}");
+            Building::default()
+        });
+        app.register_type::<Building>()
+            .register_type::<RoomConfig>()
+            .register_type::<ScanOptions>()
+            .insert_resource(building)
+            .init_resource::<RoomDialog>()
+            .init_resource::<RoomBounds>()
+            .init_resource::<LoadingScans>()
+            .add_systems(
+                Update,
+                (
+                    spawn_rooms,
+                    finish_scans.after(spawn_rooms),
+                    room_dialog_keys.run_if(not(egui_wants_keyboard)),
+                    room_dialog.after(room_dialog_keys),
+                ),
+            )
+            .add_systems(Update, tag_room_meshes.after(spawn_rooms))
+            .add_systems(Update, update_room_bounds.after(tag_room_meshes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn no_room_arguments() {
+        assert_eq!(RoomConfig::from_args(args("--verbose")).unwrap(), None);
+    }
+
+    #[test]
+    fn room_from_arguments() {
+        let config = RoomConfig::from_args(args("--room flat.glb --scale 1 --floor-offset 0.5"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            config,
+            RoomConfig {
+                path: "flat.glb".into(),
+                scale: 1.0,
+                floor_offset: 0.5,
+                ..default()
+            }
+        );
+        // other models aren't fitted like the bundled room, a metric scan stays metric
+        let config = RoomConfig::from_args(args("--scale 2 --room scan.ply --align-floor"))
+            .unwrap()
+            .unwrap();
+        assert_eq!((config.scale, config.floor_offset), (2.0, 0.0));
+        assert!(config.scan.align_floor);
+        let config = RoomConfig::from_args(args("--room room.gltf"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config, RoomConfig::default());
+    }
+
+    #[test]
+    fn bad_room_arguments() {
+        assert!(RoomConfig::from_args(args("--scale big")).is_err());
+        assert!(RoomConfig::from_args(args("--room")).is_err());
+    }
+
+    #[test]
+    fn snapshot_sees_the_floor() {
+        // a floor two units below, hidden meshes only block light
+        let floor = Arc::new(Bvh::from_triangles(vec![
+            [
+                Vec3::new(-1.0, 0.0, -1.0),
+                Vec3::new(-1.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, -1.0),
+            ],
+            [
+                Vec3::new(1.0, 0.0, -1.0),
+                Vec3::new(-1.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, 1.0),
+            ],
+        ]));
+        let below = GlobalTransform::from_translation(Vec3::NEG_Y * 2.0);
+        let snapshot = RoomSnapshot {
+            visible: vec![],
+            full: vec![(floor.clone(), below)],
+        };
+        let down = Ray {
+            origin: Vec3::ZERO,
+            direction: Vec3::NEG_Y,
+        };
+        assert!(snapshot.hit(down).is_none());
+        assert_eq!(snapshot.cast(down), Some(2.0));
+        assert!(!snapshot.line_of_sight(Vec3::ZERO, Vec3::NEG_Y * 3.0));
+        assert!(snapshot.line_of_sight(Vec3::ZERO, Vec3::NEG_Y));
+
+        let shown = RoomSnapshot {
+            visible: vec![(floor.clone(), below)],
+            full: vec![(floor.clone(), below)],
+        };
+        let hit = shown.hit(down).unwrap();
+        assert_eq!((hit.point, hit.normal), (Vec3::NEG_Y * 2.0, Vec3::Y));
+        // equal while nothing is rebuilt or moved
+        assert!(shown == shown.clone());
+        assert!(shown != snapshot);
+        let rebuilt = Arc::new(Bvh::from_triangles(vec![]));
+        let other = RoomSnapshot {
+            visible: vec![(rebuilt.clone(), below)],
+            full: vec![(rebuilt, below)],
+        };
+        assert!(shown != other);
+    }
+}
@@ -0,0 +1,633 @@
+// Rooms captured with phone LiDAR apps, which export OBJ or PLY rather than glTF
+use anyhow::{anyhow, Context, Error};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Reflect, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ScanOptions {
+    pub max_triangles: Option<usize>, // decimate denser scans down to about this many
+    pub align_floor: bool, // turn the scan upright and move the largest floor surface to y = 0
+}
+
+/// Triangles with optional per-vertex colors, as read from a scan
+#[derive(Default, Debug, PartialEq)]
+pub struct Scan {
+    pub positions: Vec<Vec3>,
+    pub colors: Option<Vec<[f32; 4]>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Scan {
+    pub fn load(path: &str, options: &ScanOptions) -> Result<Self, Error> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading {path}"))?;
+        let mut scan = if path.ends_with(".ply") {
+            parse_ply(&bytes)?
+        } else {
+            parse_obj(std::str::from_utf8(&bytes)?)?
+        };
+        if let Some(max) = options.max_triangles {
+            let before = scan.triangles.len();
+            scan.decimate(max);
+            println!(
+                "decimated {path} from {before} to {} triangles",
+                scan.triangles.len()
+            );
+        }
+        if options.align_floor {
+            scan.align_floor();
+        }
+        Ok(scan)
+    }
+
+    /// Vertex clustering: merge everything within a grid cell, growing the cell until few enough triangles are left
+    pub fn decimate(&mut self, max_triangles: usize) {
+        if self.triangles.len() <= max_triangles || self.positions.is_empty() {
+            return;
+        }
+        let (min, max) = self.bounds();
+        let mut cell = (max - min).max_element() / 1024.0;
+        loop {
+            let clustered = self.clustered(min, cell.max(f32::EPSILON));
+            if clustered.triangles.len() <= max_triangles || cell > (max - min).max_element() {
+                *self = clustered;
+                return;
+            }
+            cell *= 1.5;
+        }
+    }
+
+    fn clustered(&self, origin: Vec3, cell: f32) -> Self {
+        let mut clusters: HashMap<IVec3, u32> = HashMap::new();
+        let mut sums: Vec<(Vec3, Vec4, f32)> = vec![];
+        let remap: Vec<u32> = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let key = ((*p - origin) / cell).floor().as_ivec3();
+                let index = *clusters.entry(key).or_insert_with(|| {
+                    sums.push((Vec3::ZERO, Vec4::ZERO, 0.0));
+                    sums.len() as u32 - 1
+                });
+                let sum = &mut sums[index as usize];
+                sum.0 += *p;
+                if let Some(colors) = &self.colors {
+                    sum.1 += Vec4::from(colors[i]);
+                }
+                sum.2 += 1.0;
+                index
+            })
+            .collect();
+        let triangles = self
+            .triangles
+            .iter()
+            .map(|t| t.map(|i| remap[i as usize]))
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+        Self {
+            positions: sums.iter().map(|(p, _, n)| *p / *n).collect(),
+            colors: self
+                .colors
+                .as_ref()
+                .map(|_| sums.iter().map(|(_, c, n)| (*c / *n).to_array()).collect()),
+            triangles,
+        }
+    }
+
+    fn bounds(&self) -> (Vec3, Vec3) {
+        self.positions.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        )
+    }
+
+    /// Height with the most upward facing area, in 5cm bins
+    pub fn floor_height(&self) -> Option<f32> {
+        const BIN: f32 = 0.05;
+        let mut bins: HashMap<i32, f32> = HashMap::new();
+        for t in &self.triangles {
+            let [a, b, c] = t.map(|i| self.positions[i as usize]);
+            let cross = (b - a).cross(c - a);
+            let area = cross.length() / 2.0;
+            if area <= 0.0 || cross.normalize().y < 0.9 {
+                continue;
+            }
+            let y = (a.y + b.y + c.y) / 3.0;
+            *bins.entry((y / BIN).floor() as i32).or_default() += area;
+        }
+        bins.into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(bin, _)| (bin as f32 + 0.5) * BIN)
+    }
+
+    /// Which way is up: the axis most of the surface faces along, floors, ceilings and tabletops
+    /// all count. Pointing the way most of that area faces, since the ceiling is rarely all scanned.
+    pub fn up_axis(&self) -> Option<Vec3> {
+        // area weighted normals, binned by direction with opposite ones together
+        let mut bins: HashMap<IVec3, (Vec3, f32, f32)> = HashMap::new(); // sum, area along, area against
+        for t in &self.triangles {
+            let [a, b, c] = t.map(|i| self.positions[i as usize]);
+            let cross = (b - a).cross(c - a);
+            let area = cross.length() / 2.0;
+            if area <= 0.0 {
+                continue;
+            }
+            let normal = cross / (2.0 * area);
+            // the same sign for both faces of a plane
+            let flip = normal
+                .to_array()
+                .into_iter()
+                .find(|v| v.abs() > 1e-3)
+                .unwrap_or(1.0)
+                < 0.0;
+            let axis = if flip { -normal } else { normal };
+            let bin = bins.entry((axis * 4.0).round().as_ivec3()).or_default();
+            bin.0 += axis * area;
+            if flip {
+                bin.2 += area;
+            } else {
+                bin.1 += area;
+            }
+        }
+        let (sum, along, against) = bins
+            .into_values()
+            .max_by(|a, b| (a.1 + a.2).total_cmp(&(b.1 + b.2)))?;
+        let axis = sum.try_normalize()?;
+        Some(if along >= against { axis } else { -axis })
+    }
+
+    pub fn align_floor(&mut self) {
+        if let Some(up) = self.up_axis() {
+            let rotation = Quat::from_rotation_arc(up, Vec3::Y);
+            for p in &mut self.positions {
+                *p = rotation * *p;
+            }
+        }
+        if let Some(floor) = self.floor_height() {
+            for p in &mut self.positions {
+                p.y -= floor;
+            }
+        }
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        // smooth normals, weighted by triangle area
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+        for t in &self.triangles {
+            let [a, b, c] = t.map(|i| self.positions[i as usize]);
+            let n = (b - a).cross(c - a);
+            for i in t {
+                normals[*i as usize] += n;
+            }
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            self.positions
+                .iter()
+                .map(|p| p.to_array())
+                .collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            normals
+                .iter()
+                .map(|n| n.normalize_or_zero().to_array())
+                .collect::<Vec<_>>(),
+        );
+        if let Some(colors) = self.colors {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+        mesh.set_indices(Some(Indices::U32(self.triangles.concat())));
+        mesh
+    }
+}
+
+/// Index of an OBJ vertex reference, 1 based or negative from the end
+fn obj_index(token: &str, count: usize) -> Result<u32, Error> {
+    let i: i64 = token.split('/').next().unwrap_or_default().parse()?;
+    let i = if i < 0 { count as i64 + i } else { i - 1 };
+    if i < 0 || i >= count as i64 {
+        return Err(anyhow!("vertex {token} out of range"));
+    }
+    Ok(i as u32)
+}
+
+pub fn parse_obj(text: &str) -> Result<Scan, Error> {
+    let mut scan = Scan::default();
+    let mut colors = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let v: Vec<f32> = words.map(str::parse).collect::<Result<_, _>>()?;
+                if v.len() < 3 {
+                    return Err(anyhow!("line {}: vertex needs 3 coordinates", n + 1));
+                }
+                scan.positions.push(Vec3::new(v[0], v[1], v[2]));
+                // some scanners append r g b to every vertex
+                if v.len() >= 6 {
+                    colors.push([v[3], v[4], v[5], 1.0]);
+                }
+            }
+            Some("f") => {
+                let count = scan.positions.len();
+                let face: Vec<u32> = words
+                    .map(|w| obj_index(w, count))
+                    .collect::<Result<_, _>>()
+                    .with_context(|| format!("line {}", n + 1))?;
+                // fan out polygons
+                for i in 2..face.len() {
+                    scan.triangles.push([face[0], face[i - 1], face[i]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !colors.is_empty() && colors.len() == scan.positions.len() {
+        scan.colors = Some(colors);
+    }
+    Ok(scan)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<Self, Error> {
+        Ok(match name {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return Err(anyhow!("unknown PLY type {name}")),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, PlyType::F32 | PlyType::F64)
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    list: Option<PlyType>, // type of the count for list properties
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads scalar values one after another out of the body
+struct PlyReader<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    words: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, Error> {
+        if self.format == PlyFormat::Ascii {
+            let word = self.words.next().ok_or_else(|| anyhow!("PLY ends early"))?;
+            return Ok(word.parse()?);
+        }
+        let size = ty.size();
+        if self.bytes.len() < size {
+            return Err(anyhow!("PLY ends early"));
+        }
+        let (raw, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(raw);
+        if self.format == PlyFormat::BigEndian {
+            buf[..size].reverse();
+        }
+        Ok(match ty {
+            PlyType::I8 => buf[0] as i8 as f64,
+            PlyType::U8 => buf[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+pub fn parse_ply(bytes: &[u8]) -> Result<Scan, Error> {
+    const END: &[u8] = b"end_header";
+    let header_end = bytes
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or_else(|| anyhow!("PLY has no end_header"))?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(bytes.len(), |i| header_end + i + 1);
+    let header = std::str::from_utf8(&bytes[..header_end])?;
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in header.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    _ => return Err(anyhow!("unknown PLY format {f}")),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: vec![],
+            }),
+            ["property", "list", count, ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("property before element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                    list: Some(PlyType::parse(count)?),
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("property before element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                    list: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| anyhow!("PLY has no format line"))?;
+    let body = &bytes[body_start..];
+    let mut reader = PlyReader {
+        format,
+        bytes: body,
+        words: if format == PlyFormat::Ascii {
+            std::str::from_utf8(body)?.split_ascii_whitespace()
+        } else {
+            "".split_ascii_whitespace()
+        },
+    };
+
+    let mut scan = Scan::default();
+    let mut colors = vec![];
+    for element in &elements {
+        // integer colors are 0-255, float colors already 0-1
+        let color_scale = match element.properties.iter().find(|p| p.name == "red") {
+            Some(red) if red.ty.is_float() => 1.0,
+            _ => 255.0,
+        };
+        for _ in 0..element.count {
+            let mut values: HashMap<&str, f64> = HashMap::new();
+            let mut list: Vec<u32> = vec![];
+            for property in &element.properties {
+                match property.list {
+                    Some(count_ty) => {
+                        let count = reader.read(count_ty)? as usize;
+                        let items = (0..count)
+                            .map(|_| reader.read(property.ty).map(|v| v as u32))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        if property.name == "vertex_indices" || property.name == "vertex_index" {
+                            list = items;
+                        }
+                    }
+                    None => {
+                        values.insert(&property.name, reader.read(property.ty)?);
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |name| values.get(name).copied().unwrap_or_default() as f32;
+                    scan.positions.push(Vec3::new(get("x"), get("y"), get("z")));
+                    if values.contains_key("red") {
+                        colors.push([
+                            get("red") / color_scale,
+                            get("green") / color_scale,
+                            get("blue") / color_scale,
+                            1.0,
+                        ]);
+                    }
+                }
+                "face" => {
+                    for i in 2..list.len() {
+                        scan.triangles.push([list[0], list[i - 1], list[i]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    let count = scan.positions.len() as u32;
+    if scan.triangles.iter().flatten().any(|i| *i >= count) {
+        return Err(anyhow!("PLY face refers to a missing vertex"));
+    }
+    if !colors.is_empty() && colors.len() == scan.positions.len() {
+        scan.colors = Some(colors);
+    }
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE_OBJ: &str = "
+# unit square floor and a raised quad
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+v 0 2 0 1 0 0
+f 1 4 3 2
+f -5/1/1 -4/2/2 -1/3/3
+";
+
+    #[test]
+    fn obj_faces_fan_out() {
+        let scan = parse_obj(CUBE_OBJ).unwrap();
+        assert_eq!(scan.positions.len(), 5);
+        assert_eq!(scan.triangles, vec![[0, 3, 2], [0, 2, 1], [0, 1, 4]]);
+        // only one vertex had a color, so none are used
+        assert!(scan.colors.is_none());
+        assert!(parse_obj("v 0 0 0\nf 1 2 3").is_err());
+    }
+
+    fn ply_header(format: &str) -> String {
+        format!(
+            "ply\nformat {format} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n"
+        )
+    }
+
+    #[test]
+    fn ascii_ply_with_colors() {
+        let ply = ply_header("ascii") + "0 0 0 255 0 0\n1 0 0 0 255 0\n0 0 1 0 0 255\n3 0 2 1\n";
+        let scan = parse_ply(ply.as_bytes()).unwrap();
+        assert_eq!(scan.triangles, vec![[0, 2, 1]]);
+        assert_eq!(scan.colors.unwrap()[1], [0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn ply_colors_scale_by_their_type() {
+        // a dark uchar color is still 0-255, not already 0-1
+        let ply = ply_header("ascii") + "0 0 0 1 0 1\n1 0 0 0 0 0\n0 0 1 0 0 0\n3 0 2 1\n";
+        let scan = parse_ply(ply.as_bytes()).unwrap();
+        assert_eq!(
+            scan.colors.unwrap()[0],
+            [1.0 / 255.0, 0.0, 1.0 / 255.0, 1.0]
+        );
+
+        let ply = ply_header("ascii").replace("property uchar", "property float")
+            + "0 0 0 1 0 0.5\n1 0 0 0 0 0\n0 0 1 0 0 0\n3 0 2 1\n";
+        let scan = parse_ply(ply.as_bytes()).unwrap();
+        assert_eq!(scan.colors.unwrap()[0], [1.0, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn binary_ply_matches_ascii() {
+        let mut ply = ply_header("binary_little_endian").into_bytes();
+        for (p, c) in [
+            ([0.0f32, 0.0, 0.0], [255u8, 0, 0]),
+            ([1.0, 0.0, 0.0], [0, 255, 0]),
+            ([0.0, 0.0, 1.0], [0, 0, 255]),
+        ] {
+            for v in p {
+                ply.extend(v.to_le_bytes());
+            }
+            ply.extend(c);
+        }
+        ply.push(3);
+        for i in [0i32, 2, 1] {
+            ply.extend(i.to_le_bytes());
+        }
+        let binary = parse_ply(&ply).unwrap();
+        let ascii = parse_ply(
+            (ply_header("ascii") + "0 0 0 255 0 0\n1 0 0 0 255 0\n0 0 1 0 0 255\n3 0 2 1\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(binary, ascii);
+    }
+
+    /// Flat grid of `n` x `n` quads at height `y`
+    fn grid(n: u32, y: f32) -> Scan {
+        let mut scan = Scan::default();
+        for z in 0..=n {
+            for x in 0..=n {
+                scan.positions
+                    .push(Vec3::new(x as f32 / n as f32, y, z as f32 / n as f32));
+            }
+        }
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                scan.triangles.push([i, i + n + 1, i + 1]);
+                scan.triangles.push([i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+        scan
+    }
+
+    #[test]
+    fn decimate_to_budget() {
+        let mut scan = grid(64, 0.0);
+        assert_eq!(scan.triangles.len(), 64 * 64 * 2);
+        scan.decimate(500);
+        assert!(scan.triangles.len() <= 500 && !scan.triangles.is_empty());
+        let (min, max) = scan.bounds();
+        // still roughly covers the same area
+        assert!(min.x < 0.1 && max.x > 0.9);
+    }
+
+    #[test]
+    fn floor_moves_to_zero() {
+        let mut scan = grid(4, -1.23);
+        // a smaller table top above the floor
+        let table = grid(2, -0.5);
+        let offset = scan.positions.len() as u32;
+        scan.positions.extend(
+            table
+                .positions
+                .iter()
+                .map(|p| *p * Vec3::new(0.5, 1.0, 0.5)),
+        );
+        scan.triangles
+            .extend(table.triangles.iter().map(|t| t.map(|i| i + offset)));
+        scan.align_floor();
+        let floor = scan.positions[0].y;
+        assert!(floor.abs() <= 0.05, "floor ended up at {floor}");
+    }
+
+    #[test]
+    fn z_up_scan_turns_upright() {
+        // floor and a table top exported with z up, like many scanning apps do
+        let mut scan = grid(4, -1.0);
+        let table = grid(2, -0.5);
+        let offset = scan.positions.len() as u32;
+        scan.positions.extend(
+            table
+                .positions
+                .iter()
+                .map(|p| *p * Vec3::new(0.5, 1.0, 0.5)),
+        );
+        scan.triangles
+            .extend(table.triangles.iter().map(|t| t.map(|i| i + offset)));
+        let z_up = Quat::from_rotation_arc(Vec3::Y, Vec3::Z);
+        scan.positions.iter_mut().for_each(|p| *p = z_up * *p);
+        assert!(scan.up_axis().unwrap().abs_diff_eq(Vec3::Z, 1e-5));
+
+        scan.align_floor();
+        assert!(scan.up_axis().unwrap().abs_diff_eq(Vec3::Y, 1e-5));
+        let floor = scan.positions[0].y;
+        assert!(floor.abs() <= 0.05, "floor ended up at {floor}");
+        // the table is still above it
+        assert!(scan.positions[offset as usize].y > 0.4);
+    }
+}
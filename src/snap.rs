@@ -0,0 +1,135 @@
+// Dragged lamps sit on whatever room surface is under the cursor: floor, shelf, wall or ceiling
+use crate::bulb::Lamp;
+use crate::hover::{drag_system, Dragged, MouseRay};
+use crate::room::RoomGeometry;
+use bevy::prelude::*;
+use bevy_debug_grid::{Grid, SubGrid};
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct SurfaceSnap {
+    pub enabled: bool,
+    pub align_to_normal: bool, // stand lamps upright on floors, sideways on walls, hanging from ceilings
+    pub grid: bool,            // always snap to the floor grid, otherwise only while Ctrl is held
+}
+
+impl Default for SurfaceSnap {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            align_to_normal: true,
+            grid: false,
+        }
+    }
+}
+
+/// Smallest cell of the debug floor grid, including its sub grid lines
+fn grid_step(grid: &Grid, sub: Option<&SubGrid>) -> f32 {
+    grid.spacing / (sub.map_or(0, |s| s.count) + 1) as f32
+}
+
+/// Snap `point` to the grid without lifting it off the surface it lies on
+fn snap_to_grid(point: Vec3, normal: Vec3, step: f32) -> Vec3 {
+    let snapped = (point / step).round() * step;
+    let snapped = Vec3::new(snapped.x, point.y, snapped.z);
+    snapped - normal * (snapped - point).dot(normal)
+}
+
+/// `rotation` tipped over so its up points along `normal`, keeping its turn around up
+fn align_up(rotation: Quat, normal: Vec3) -> Quat {
+    let tilt = Quat::from_rotation_arc(Vec3::Y, (rotation * Vec3::Y).normalize());
+    let turn = tilt.inverse() * rotation;
+    Quat::from_rotation_arc(Vec3::Y, normal) * turn
+}
+
+fn snap_to_surface(
+    settings: Res<SurfaceSnap>,
+    keys: Res<Input<KeyCode>>,
+    geometry: RoomGeometry,
+    ray_query: Query<&MouseRay>,
+    grids: Query<(&Grid, Option<&SubGrid>)>,
+    mut lamps: Query<(&mut Transform, &Dragged), With<Lamp>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let Ok(MouseRay { ray, .. }) = ray_query.get_single() else {
+        return;
+    };
+    // pointing past the room, the lamp keeps sliding on its plane
+    let Some(hit) = geometry.hit(*ray) else {
+        return;
+    };
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let step = grids
+        .get_single()
+        .ok()
+        .filter(|_| settings.grid || ctrl)
+        .map(|(grid, sub)| grid_step(grid, sub));
+    for (mut transform, dragged) in lamps.iter_mut() {
+        // a gizmo handle keeps the lamp on its axis or plane
+        if dragged.gizmo {
+            continue;
+        }
+        transform.translation = match step {
+            Some(step) => snap_to_grid(hit.point, hit.normal, step),
+            None => hit.point,
+        };
+        if settings.align_to_normal {
+            transform.rotation = align_up(transform.rotation, hit.normal);
+        }
+    }
+}
+
+pub struct SnapPlugin;
+
+impl Plugin for SnapPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SurfaceSnap>()
+            .init_resource::<SurfaceSnap>()
+            .add_systems(Update, snap_to_surface.after(drag_system));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_snap_stays_on_surface() {
+        // floor: x and z snap, height stays
+        let p = snap_to_grid(Vec3::new(1.3, 0.2, -2.6), Vec3::Y, 1.0);
+        assert_eq!(p, Vec3::new(1.0, 0.2, -3.0));
+        // wall facing +x: z snaps, x stays on the wall
+        let p = snap_to_grid(Vec3::new(-4.2, 1.7, 0.4), Vec3::X, 0.5);
+        assert_eq!(p, Vec3::new(-4.2, 1.7, 0.5));
+    }
+
+    #[test]
+    fn align_keeps_turn() {
+        let turned = Quat::from_rotation_y(0.7);
+        // already upright: unchanged
+        let upright = align_up(turned, Vec3::Y);
+        assert!(upright.angle_between(turned) < 1e-4);
+        // on a ceiling the lamp hangs upside down
+        let hanging = align_up(turned, Vec3::NEG_Y);
+        assert!((hanging * Vec3::Y).distance(Vec3::NEG_Y) < 1e-4);
+        // and back on the floor it is turned the same way as before
+        let back = align_up(hanging, Vec3::Y);
+        assert!(back.angle_between(turned) < 1e-4);
+    }
+
+    #[test]
+    fn grid_step_counts_sub_lines() {
+        let grid = Grid {
+            spacing: 10.0,
+            ..default()
+        };
+        let sub = SubGrid {
+            count: 9,
+            color: Color::GRAY,
+        };
+        assert_eq!(grid_step(&grid, Some(&sub)), 1.0);
+        assert_eq!(grid_step(&grid, None), 10.0);
+    }
+}
@@ -0,0 +1,359 @@
+// Touch screens: turns fingers into the same pointer input the mouse gives,
+// so picking, clicks and drags go through hover.rs unchanged
+use crate::bulb::Lamp;
+use crate::camera::OrbitCamera;
+use crate::hover::{update_mouse_ray, MouseRay, PickResult};
+use crate::hue::BulbState;
+use bevy::input::touch::{TouchInput, TouchPhase};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use std::collections::{HashMap, VecDeque};
+
+const TAP_SLOP: f32 = 10.0; // pixels a finger may wander before a tap becomes a drag
+const LONG_PRESS_TIME: f32 = 0.6; // seconds
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    /// Aim the pointer, like moving the mouse
+    Point(Vec2),
+    /// Left button down / up at a position
+    Press(Vec2),
+    Release(Vec2),
+    LongPress(Vec2),
+    /// Two fingers, `pinch` is the change in their distance as a ratio, `rotate` in radians
+    Camera {
+        pinch: f32,
+        rotate: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Idle,
+    /// One finger down that hasn't decided yet between tap, drag and long press
+    Pending {
+        id: u64,
+        start: Vec2,
+        at: f32,
+    },
+    Dragging {
+        id: u64,
+    },
+    /// Long press fired, nothing more until the finger lifts
+    Held,
+    TwoFinger {
+        a: u64,
+        b: u64,
+    },
+    /// A gesture ended with fingers still down, wait until they all lift
+    Lifted,
+}
+
+/// Gesture recognizer, fed raw touches and polled once a frame
+pub struct Gestures {
+    phase: Phase,
+    fingers: HashMap<u64, Vec2>,
+    out: Vec<Gesture>,
+}
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Idle,
+            fingers: HashMap::new(),
+            out: vec![],
+        }
+    }
+}
+
+impl Gestures {
+    pub fn touch(&mut self, input: &TouchInput, now: f32) {
+        let (id, pos) = (input.id, input.position);
+        match input.phase {
+            TouchPhase::Started => {
+                self.fingers.insert(id, pos);
+                match (self.phase, self.fingers.len()) {
+                    (Phase::Idle, 1) => {
+                        self.out.push(Gesture::Point(pos));
+                        self.phase = Phase::Pending {
+                            id,
+                            start: pos,
+                            at: now,
+                        };
+                    }
+                    (Phase::Pending { .. } | Phase::Dragging { .. } | Phase::Held, 2) => {
+                        // a second finger takes over for the camera
+                        if let Phase::Dragging { id: first } = self.phase {
+                            self.out.push(Gesture::Release(self.fingers[&first]));
+                        }
+                        let a = *self.fingers.keys().find(|k| **k != id).unwrap();
+                        self.phase = Phase::TwoFinger { a, b: id };
+                    }
+                    _ => {}
+                }
+            }
+            TouchPhase::Moved => {
+                let Some(last) = self.fingers.insert(id, pos) else {
+                    return;
+                };
+                match self.phase {
+                    Phase::Pending { id: p, start, .. }
+                        if p == id && start.distance(pos) > TAP_SLOP =>
+                    {
+                        // press where the finger went down, so the drag grabs what was under it
+                        self.out.push(Gesture::Press(start));
+                        self.out.push(Gesture::Point(pos));
+                        self.phase = Phase::Dragging { id };
+                    }
+                    Phase::Dragging { id: d } if d == id => self.out.push(Gesture::Point(pos)),
+                    Phase::TwoFinger { a, b } if a == id || b == id => {
+                        let other = self.fingers[&if a == id { b } else { a }];
+                        let before = last - other;
+                        let after = pos - other;
+                        if before.length() > f32::EPSILON && after.length() > f32::EPSILON {
+                            self.out.push(Gesture::Camera {
+                                pinch: after.length() / before.length(),
+                                rotate: before.angle_between(after),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Canceled => {
+                self.fingers.remove(&id);
+                let ended = input.phase == TouchPhase::Ended;
+                match self.phase {
+                    Phase::Pending { id: p, start, .. } if p == id && ended => {
+                        self.out.push(Gesture::Press(start));
+                        self.out.push(Gesture::Release(start));
+                        self.phase = Phase::Lifted;
+                    }
+                    Phase::Dragging { id: d } if d == id => {
+                        self.out.push(Gesture::Release(pos));
+                        self.phase = Phase::Lifted;
+                    }
+                    Phase::Idle => {}
+                    _ => self.phase = Phase::Lifted,
+                }
+                if self.fingers.is_empty() {
+                    self.phase = Phase::Idle;
+                }
+            }
+        }
+    }
+
+    /// Gestures recognized since the last poll, including long presses that timed out by `now`
+    pub fn poll(&mut self, now: f32) -> Vec<Gesture> {
+        if let Phase::Pending { start, at, .. } = self.phase {
+            if now - at >= LONG_PRESS_TIME {
+                self.out.push(Gesture::LongPress(start));
+                self.phase = Phase::Held;
+            }
+        }
+        std::mem::take(&mut self.out)
+    }
+}
+
+/// Sent when a finger rests on a `Hoverable`
+#[derive(Event, Clone, Debug)]
+pub struct LongPress {
+    pub entity: Entity,
+    pub hit: Vec3,
+}
+
+#[derive(Resource, Default)]
+struct TouchState {
+    gestures: Gestures,
+    // button changes are spread over frames, a press and release in one frame would look like nothing
+    pending: VecDeque<Gesture>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_touches(
+    time: Res<Time>,
+    mut state: ResMut<TouchState>,
+    mut touch_events: EventReader<TouchInput>,
+    primary_window: Query<Entity, With<PrimaryWindow>>,
+    mut ray_query: Query<&mut MouseRay>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    pick: Res<PickResult>,
+    mut long_press_events: EventWriter<LongPress>,
+    mut camera_events: EventWriter<TouchCamera>,
+) {
+    let now = time.elapsed_seconds();
+    for event in touch_events.iter() {
+        state.gestures.touch(event, now);
+    }
+    let recognized = state.gestures.poll(now);
+    state.pending.extend(recognized);
+
+    let (Ok(window), Ok(mut mouse_ray)) = (primary_window.get_single(), ray_query.get_single_mut())
+    else {
+        return;
+    };
+    while let Some(gesture) = state.pending.pop_front() {
+        match gesture {
+            Gesture::Point(pos) => mouse_ray.point_at(window, pos),
+            Gesture::Press(pos) => {
+                mouse_ray.point_at(window, pos);
+                mouse_button_input.press(MouseButton::Left);
+                break;
+            }
+            Gesture::Release(pos) => {
+                mouse_ray.point_at(window, pos);
+                mouse_button_input.release(MouseButton::Left);
+                break;
+            }
+            Gesture::LongPress(_) => {
+                // the finger has rested there, so the last pick is what's under it
+                if let Some(p) = pick.0 {
+                    long_press_events.send(LongPress {
+                        entity: p.entity,
+                        hit: p.point,
+                    });
+                }
+            }
+            Gesture::Camera { pinch, rotate } => camera_events.send(TouchCamera { pinch, rotate }),
+        }
+    }
+}
+
+/// Two finger pinch and twist
+#[derive(Event, Clone, Debug)]
+pub struct TouchCamera {
+    pub pinch: f32,
+    pub rotate: f32,
+}
+
+/// Spin the camera around the point it looks at on the floor and dolly towards it
+fn touch_camera(mut events: EventReader<TouchCamera>, mut cameras: Query<&mut OrbitCamera>) {
+    for event in events.iter() {
+        for mut camera in cameras.iter_mut() {
+            camera.yaw -= event.rotate;
+            // spreading the fingers zooms in
+            camera.distance /= event.pinch.clamp(0.5, 2.0);
+        }
+    }
+}
+
+fn identify_bulbs(
+    mut events: EventReader<LongPress>,
+    lamps: Query<&Lamp>,
+    bulb_state: Option<Res<BulbState>>,
+) {
+    for event in events.iter() {
+        let (Ok(lamp), Some(bulb_state)) = (lamps.get(event.entity), &bulb_state) else {
+            continue;
+        };
+        println!("identifying bulb {} at {}", lamp.index, event.hit);
+        bulb_state.identify(lamp.index);
+    }
+}
+
+pub struct TouchPlugin;
+
+impl Plugin for TouchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TouchState>()
+            .add_event::<LongPress>()
+            .add_event::<TouchCamera>()
+            .add_systems(Update, apply_touches.before(update_mouse_ray))
+            .add_systems(Update, (touch_camera, identify_bulbs).after(apply_touches));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f32, y: f32) -> TouchInput {
+        TouchInput {
+            phase,
+            position: Vec2::new(x, y),
+            force: None,
+            id,
+        }
+    }
+
+    #[test]
+    fn tap() {
+        let mut g = Gestures::default();
+        g.touch(&touch(0, TouchPhase::Started, 100.0, 100.0), 0.0);
+        g.touch(&touch(0, TouchPhase::Moved, 102.0, 101.0), 0.05);
+        g.touch(&touch(0, TouchPhase::Ended, 102.0, 101.0), 0.1);
+        let at = Vec2::new(100.0, 100.0);
+        assert_eq!(
+            g.poll(0.1),
+            vec![Gesture::Point(at), Gesture::Press(at), Gesture::Release(at)]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let mut g = Gestures::default();
+        g.touch(&touch(0, TouchPhase::Started, 50.0, 50.0), 0.0);
+        assert_eq!(g.poll(0.3), vec![Gesture::Point(Vec2::splat(50.0))]);
+        assert_eq!(g.poll(0.7), vec![Gesture::LongPress(Vec2::splat(50.0))]);
+        // lifting after a long press is not a tap
+        g.touch(&touch(0, TouchPhase::Ended, 50.0, 50.0), 0.8);
+        assert!(g.poll(0.8).is_empty());
+    }
+
+    #[test]
+    fn drag() {
+        let mut g = Gestures::default();
+        g.touch(&touch(0, TouchPhase::Started, 0.0, 0.0), 0.0);
+        g.touch(&touch(0, TouchPhase::Moved, 30.0, 0.0), 0.1);
+        g.touch(&touch(0, TouchPhase::Moved, 60.0, 0.0), 0.2);
+        g.touch(&touch(0, TouchPhase::Ended, 60.0, 0.0), 0.3);
+        assert_eq!(
+            g.poll(0.3),
+            vec![
+                Gesture::Point(Vec2::ZERO),
+                Gesture::Press(Vec2::ZERO),
+                Gesture::Point(Vec2::new(30.0, 0.0)),
+                Gesture::Point(Vec2::new(60.0, 0.0)),
+                Gesture::Release(Vec2::new(60.0, 0.0)),
+            ]
+        );
+        // no long press once dragging
+        assert!(g.poll(5.0).is_empty());
+    }
+
+    #[test]
+    fn pinch_and_rotate() {
+        let mut g = Gestures::default();
+        g.touch(&touch(0, TouchPhase::Started, 0.0, 0.0), 0.0);
+        g.touch(&touch(1, TouchPhase::Started, 100.0, 0.0), 0.05);
+        g.poll(0.05);
+        // spread to twice the distance
+        g.touch(&touch(1, TouchPhase::Moved, 200.0, 0.0), 0.1);
+        // then a quarter turn
+        g.touch(&touch(1, TouchPhase::Moved, 0.0, 200.0), 0.2);
+        let out = g.poll(0.2);
+        let Gesture::Camera { pinch, rotate } = out[0] else {
+            panic!("expected a camera gesture, got {:?}", out[0]);
+        };
+        assert!((pinch - 2.0).abs() < 1e-5 && rotate.abs() < 1e-5);
+        let Gesture::Camera { pinch, rotate } = out[1] else {
+            panic!("expected a camera gesture, got {:?}", out[1]);
+        };
+        assert!((pinch - 1.0).abs() < 1e-5);
+        assert!((rotate - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        // lifting one finger doesn't start a drag with the other
+        g.touch(&touch(1, TouchPhase::Ended, 0.0, 200.0), 0.3);
+        g.touch(&touch(0, TouchPhase::Moved, 50.0, 0.0), 0.35);
+        assert!(g.poll(1.0).is_empty());
+    }
+
+    #[test]
+    fn second_finger_releases_drag() {
+        let mut g = Gestures::default();
+        g.touch(&touch(0, TouchPhase::Started, 0.0, 0.0), 0.0);
+        g.touch(&touch(0, TouchPhase::Moved, 30.0, 0.0), 0.1);
+        g.poll(0.1);
+        g.touch(&touch(1, TouchPhase::Started, 100.0, 100.0), 0.2);
+        assert_eq!(g.poll(0.2), vec![Gesture::Release(Vec2::new(30.0, 0.0))]);
+    }
+}